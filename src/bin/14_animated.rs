@@ -5,6 +5,7 @@ use std::{
     fmt::Display,
     io::{self, Write as IoWrite},
     ops::{Index, IndexMut},
+    time::{Duration, Instant},
 };
 
 use clap::{Parser, ValueEnum};
@@ -13,6 +14,8 @@ use rand::prelude::*;
 use termion::{
     color::{self, Rgb},
     cursor::{Goto, HideCursor},
+    event::Key,
+    input::TermRead,
     raw::IntoRawMode,
 };
 use utils::*;
@@ -24,6 +27,11 @@ const ROCK_COLOR: Rgb = Rgb(216, 216, 216);
 //const SAND_COLOR: Rgb = Rgb(245, 213, 159);
 
 const FPS: u64 = 30;
+const MAX_FPS: u64 = 1000;
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+const SCROLL_STEP: usize = 10;
+
+const CONTROLS: &str = "space: pause  s: step  +/-: speed  f: floor  ←/→: scroll  c: center  q: quit";
 
 #[derive(Parser)]
 struct Args {
@@ -137,9 +145,33 @@ impl Grid {
         matches!(self[self.src], Air)
     }
 
-    fn add_floor(&mut self) {
+    fn add_floor(&mut self, sand: &mut VecDeque<(Pos, Particle)>) {
         self.grid.push(vec![Air; self.len().0].into());
         self.has_floor = true;
+        // Grains that reached the bottom row without a floor were left hanging there, so they have to
+        // fall onto the new floor first.
+        self.requeue(sand);
+    }
+
+    fn remove_floor(&mut self, sand: &mut VecDeque<(Pos, Particle)>) {
+        self.grid.pop();
+        self.has_floor = false;
+        // The grains lying on the floor drop into the abyss along with the bottom row and are gone.
+        // Everything above them has to start falling again.
+        self.requeue(sand);
+    }
+
+    /// Puts every grain back in the queue, bottom rows first so that each one falls into the space
+    /// left by those below it.
+    fn requeue(&self, sand: &mut VecDeque<(Pos, Particle)>) {
+        sand.clear();
+        for (y, row) in self.grid.iter().enumerate().rev() {
+            for (x, &particle) in row.iter().enumerate() {
+                if matches!(particle, Sand(_)) {
+                    sand.push_back(((x, y), particle));
+                }
+            }
+        }
     }
 
     fn extend_left(&mut self, n: usize) {
@@ -157,11 +189,10 @@ impl Grid {
         }
     }
 
-    fn display(&self) {
-        let mut stdout = HideCursor::from(io::stdout()).into_raw_mode().unwrap();
-        for (y, row) in self.grid.iter().enumerate() {
+    fn display(&self, stdout: &mut impl IoWrite, view: &Viewport, status: &str) {
+        for (y, row) in self.grid.iter().enumerate().take(view.height) {
             write!(stdout, "{}", Goto(1, y as u16 + 1)).unwrap();
-            for (x, &sqr) in row.iter().enumerate() {
+            for (x, &sqr) in row.iter().enumerate().skip(view.x).take(view.width) {
                 if (x, y) == self.src && !matches!(sqr, Sand(_)) {
                     write!(stdout, "+").unwrap();
                 } else {
@@ -169,65 +200,171 @@ impl Grid {
                 }
             }
         }
+        let status: String = status.chars().take(view.width).collect();
+        write!(
+            stdout,
+            "{}{}{}{status}",
+            Goto(1, view.height as u16 + 1),
+            termion::style::Reset,
+            termion::clear::CurrentLine
+        )
+        .unwrap();
+        stdout.flush().unwrap();
     }
 
-    fn sim_parallel(&mut self, fps: u64) {
+    fn tick(&mut self, sand: &mut VecDeque<(Pos, Particle)>, should_spawn: &mut bool) {
+        let mut next_queue: VecDeque<(Pos, Particle)> = VecDeque::with_capacity(sand.len());
+        while let Some((mut pos, particle)) = sand.pop_front() {
+            if pos.1 == self.len().1 - 1 {
+                if !self.has_floor {
+                    *should_spawn = false;
+                }
+                continue;
+            } else if pos.0 == 0 {
+                if self.has_floor {
+                    self.extend_left(1);
+                    next_queue.iter_mut().for_each(|p| p.0.0 += 1);
+                    sand.iter_mut().for_each(|p| p.0.0 += 1);
+                    pos.0 += 1;
+                } else {
+                    *should_spawn = false;
+                    self[pos] = Air;
+                    continue;
+                }
+            } else if pos.0 == self.len().0 - 1 {
+                if self.has_floor {
+                    self.extend_right(1);
+                } else {
+                    *should_spawn = false;
+                    self[pos] = Air;
+                    continue;
+                }
+            }
+            match next_pos(pos).iter().find(|&&p| matches!(self[p], Air)) {
+                Some(&new_pos) => {
+                    self[pos] = Air;
+                    self[new_pos] = particle;
+                    next_queue.push_back((new_pos, particle));
+                }
+                None => self[pos] = particle,
+            }
+        }
+        *sand = next_queue;
+        if *should_spawn && self.can_spawn() {
+            sand.push_back((self.src, Particle::random_sand()));
+        }
+    }
+
+    fn sim_parallel(&mut self, mut fps: u64) {
+        let mut stdout = HideCursor::from(io::stdout().into_raw_mode().unwrap());
+        let mut keys = termion::async_stdin().keys();
         let src = self.src;
         let mut sand = VecDeque::from([(src, Particle::random_sand())]);
         let mut should_spawn = true;
-        println!("{}", termion::clear::All);
-        self.display();
+        let mut paused = false;
+        let mut view = Viewport::new();
+        write!(stdout, "{}", termion::clear::All).unwrap();
         self[src] = sand[0].1;
-        while !sand.is_empty() {
-            let mut next_queue: VecDeque<((usize, usize), Particle)> = VecDeque::with_capacity(sand.len());
-            while let Some((mut pos, particle)) = sand.pop_front() {
-                if pos.1 == self.len().1 - 1 {
-                    if !self.has_floor {
-                        should_spawn = false;
-                    }
-                    continue;
-                } else if pos.0 == 0 {
-                    if self.has_floor {
-                        self.extend_left(1);
-                        next_queue.iter_mut().for_each(|p| p.0.0 += 1);
-                        sand.iter_mut().for_each(|p| p.0.0 += 1);
-                        pos.0 += 1;
-                    } else {
-                        should_spawn = false;
-                        self[pos] = Air;
-                        continue;
+        let mut last_tick = Instant::now();
+        let mut redraw = true;
+        'sim: loop {
+            let mut step = false;
+            for key in keys.by_ref().flatten() {
+                match key {
+                    Key::Char('q') | Key::Esc | Key::Ctrl('c') => break 'sim,
+                    Key::Char(' ') | Key::Char('p') => paused = !paused,
+                    Key::Char('s') | Key::Char('.') => {
+                        paused = true;
+                        step = true;
                     }
-                } else if pos.0 == self.len().0 - 1 {
-                    if self.has_floor {
-                        self.extend_right(1);
-                    } else {
-                        should_spawn = false;
-                        self[pos] = Air;
-                        continue;
+                    Key::Char('+') | Key::Char('=') => fps = (fps * 2).min(MAX_FPS),
+                    Key::Char('-') => fps = (fps / 2).max(1),
+                    Key::Char('f') => {
+                        if self.has_floor {
+                            self.remove_floor(&mut sand);
+                        } else {
+                            self.add_floor(&mut sand);
+                            should_spawn = true;
+                        }
+                        write!(stdout, "{}", termion::clear::All).unwrap();
                     }
+                    Key::Left | Key::Char('h') => view.scroll_left(SCROLL_STEP),
+                    Key::Right | Key::Char('l') => view.scroll_right(SCROLL_STEP),
+                    Key::Char('c') => view.follow = true,
+                    _ => continue,
                 }
-                match next_pos(pos).iter().find(|&&p| matches!(self[p], Air)) {
-                    Some(&new_pos) => {
-                        self[pos] = Air;
-                        self[new_pos] = particle;
-                        next_queue.push_back((new_pos, particle));
-                    }
-                    None => self[pos] = particle,
-                }
+                redraw = true;
+            }
+            if step || (!paused && last_tick.elapsed() >= Duration::from_millis(1000 / fps)) {
+                let old_src = self.src.0;
+                self.tick(&mut sand, &mut should_spawn);
+                view.x += self.src.0 - old_src;
+                last_tick = Instant::now();
+                redraw = true;
+            }
+            if view.resize(self) {
+                write!(stdout, "{}", termion::clear::All).unwrap();
+                redraw = true;
             }
-            sand = next_queue;
-            if should_spawn && self.can_spawn() {
-                sand.push_back((self.src, Particle::random_sand()));
+            if redraw {
+                let state = match (sand.is_empty(), paused) {
+                    (true, _) => "settled",
+                    (false, true) => "paused",
+                    (false, false) => "running",
+                };
+                let floor = if self.has_floor { "on" } else { "off" };
+                let status = format!("{state} | fps: {fps} | floor: {floor} | {CONTROLS}");
+                self.display(&mut stdout, &view, &status);
+                redraw = false;
             }
-            std::thread::sleep(std::time::Duration::from_millis(1000 / fps));
-            self.display();
+            std::thread::sleep(POLL_INTERVAL);
         }
+        write!(
+            stdout,
+            "{}{}{}{}",
+            termion::style::Reset,
+            Goto(1, view.height as u16 + 1),
+            termion::clear::CurrentLine,
+            termion::cursor::Show
+        )
+        .unwrap();
+        stdout.flush().unwrap();
     }
 }
 
-impl Drop for Grid {
-    fn drop(&mut self) {
-        println!("{}{}{}", termion::style::Reset, Goto(1, self.len().1 as u16), termion::cursor::Restore)
+struct Viewport {
+    x: usize,
+    width: usize,
+    height: usize,
+    follow: bool,
+}
+
+impl Viewport {
+    fn new() -> Viewport {
+        Viewport { x: 0, width: 0, height: 0, follow: true }
+    }
+
+    fn scroll_left(&mut self, n: usize) {
+        self.x = self.x.saturating_sub(n);
+        self.follow = false;
+    }
+
+    fn scroll_right(&mut self, n: usize) {
+        self.x += n;
+        self.follow = false;
+    }
+
+    /// Fits the viewport to the current terminal size and grid width. Returns whether the terminal was resized.
+    fn resize(&mut self, grid: &Grid) -> bool {
+        let (cols, rows) = termion::terminal_size().unwrap_or((80, 24));
+        let (width, height) = (cols as usize, rows.saturating_sub(1).max(1) as usize);
+        let resized = (width, height) != (self.width, self.height);
+        (self.width, self.height) = (width, height);
+        if self.follow {
+            self.x = grid.src.0.saturating_sub(width / 2);
+        }
+        self.x = self.x.min(grid.len().0.saturating_sub(width));
+        resized
     }
 }
 
@@ -239,7 +376,7 @@ fn main() {
     };
     let mut grid = Grid::new(input.lines().map(parse_path).collect());
     if args.floor {
-        grid.add_floor();
+        grid.add_floor(&mut VecDeque::new());
     }
     match args.fps {
         Some(fps) => grid.sim_parallel(fps),
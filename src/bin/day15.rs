@@ -1,18 +1,113 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use clap::{Parser, ValueEnum};
 use itertools::Itertools;
 
 use utils::*;
 
 const UPPER_BOUND: isize = 4_000_000;
 const Y_TARGET: isize = 2_000_000;
+const TEST_UPPER_BOUND: isize = 20;
+const TEST_Y_TARGET: isize = 10;
+const TUNING_FACTOR: isize = 4_000_000;
+const IMAGE_WIDTH: usize = 1000;
+
+const UNCOVERED_COLOR: [u8; 3] = [30, 30, 30];
+const COVERED_COLOR: [u8; 3] = [90, 90, 110];
+const BORDER_COLOR: [u8; 3] = [80, 200, 120];
+const SENSOR_COLOR: [u8; 3] = [230, 60, 60];
+const BEACON_COLOR: [u8; 3] = [70, 130, 240];
+const DISTRESS_COLOR: [u8; 3] = [255, 220, 0];
+
+const TEST: &str = "Sensor at x=2, y=18: closest beacon is at x=-2, y=15
+Sensor at x=9, y=16: closest beacon is at x=10, y=16
+Sensor at x=13, y=2: closest beacon is at x=15, y=3
+Sensor at x=12, y=14: closest beacon is at x=10, y=16
+Sensor at x=10, y=20: closest beacon is at x=10, y=16
+Sensor at x=14, y=17: closest beacon is at x=10, y=16
+Sensor at x=8, y=7: closest beacon is at x=2, y=10
+Sensor at x=2, y=0: closest beacon is at x=2, y=10
+Sensor at x=0, y=11: closest beacon is at x=2, y=10
+Sensor at x=20, y=14: closest beacon is at x=25, y=17
+Sensor at x=17, y=20: closest beacon is at x=21, y=22
+Sensor at x=16, y=7: closest beacon is at x=15, y=3
+Sensor at x=14, y=3: closest beacon is at x=15, y=3
+Sensor at x=20, y=1: closest beacon is at x=15, y=3";
+
+#[derive(Parser)]
+struct Args {
+    #[arg(value_enum, default_value_t = InputType::Long)]
+    input: InputType,
+    #[arg(long)]
+    y_target: Option<isize>,
+    #[arg(long)]
+    upper_bound: Option<isize>,
+    /// Print the map of the window `min_x,min_y,max_x,max_y`
+    #[arg(long)]
+    window: Option<Window>,
+    /// Write a downsampled PPM image of the window (the search area if no window is given)
+    #[arg(long)]
+    image: Option<PathBuf>,
+    #[arg(long, default_value_t = IMAGE_WIDTH)]
+    image_width: usize,
+}
+
+#[derive(Clone, ValueEnum)]
+enum InputType {
+    Simple,
+    Long,
+}
 
 type Pos = (isize, isize);
+type Interval = (isize, isize);
+
+#[derive(Clone, Copy)]
+struct Window {
+    min: Pos,
+    max: Pos,
+}
+
+impl FromStr for Window {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let coords: Vec<isize> = s
+            .split(',')
+            .map(|n| n.trim().parse().map_err(|e| format!("invalid coordinate {n:?}: {e}")))
+            .try_collect()?;
+        match coords[..] {
+            [x0, y0, x1, y1] if x0 <= x1 && y0 <= y1 => Ok(Window {
+                min: (x0, y0),
+                max: (x1, y1),
+            }),
+            [_, _, _, _] => Err("expected min_x <= max_x and min_y <= max_y".to_string()),
+            _ => Err("expected min_x,min_y,max_x,max_y".to_string()),
+        }
+    }
+}
+
+impl Window {
+    fn size(&self) -> (usize, usize) {
+        ((self.max.0 - self.min.0 + 1) as usize, (self.max.1 - self.min.1 + 1) as usize)
+    }
+}
 
 fn dist(a: Pos, b: Pos) -> isize {
     (a.0 - b.0).abs() + (a.1 - b.1).abs()
 }
 
-fn valid_pos(pos: &Pos) -> bool {
-    matches!(pos, (0..=UPPER_BOUND, 0..=UPPER_BOUND))
+/// Maps `(x, y)` to `(u, v) = (x + y, x - y)`, where the diamond edges become axis aligned.
+fn rotate(pos: Pos) -> (isize, isize) {
+    (pos.0 + pos.1, pos.0 - pos.1)
+}
+
+/// Inverse of `rotate`, or `None` if `(u, v)` does not lie on the integer grid.
+fn unrotate((u, v): (isize, isize)) -> Option<Pos> {
+    ((u + v) % 2 == 0).then(|| ((u + v) / 2, (u - v) / 2))
 }
 
 struct Sensor {
@@ -44,52 +139,188 @@ impl Sensor {
         dist(pos, self.pos) <= self.dist
     }
 
-    fn border(&self) -> impl Iterator<Item = Pos> + '_ {
-        let pos = self.pos;
-        let x_range = (pos.0 - self.dist - 1).max(0)..(pos.0 + self.dist + 1).min(UPPER_BOUND);
-        let y = move |x: isize, sign| pos.1 + sign * (self.dist + 1 - (pos.0 - x).abs());
-        x_range
-            .clone()
-            .map(move |x| (x, y(x, -1)))
-            .chain(x_range.map(move |x| (x, y(x, 1))))
+    /// The inclusive range of x values covered on row `y`.
+    fn row_interval(&self, y: isize) -> Option<Interval> {
+        let r = self.dist - (y - self.pos.1).abs();
+        (r >= 0).then_some((self.pos.0 - r, self.pos.0 + r))
+    }
+
+    /// The diagonals just outside the sensor's reach, as `u` and `v` values in rotated coordinates.
+    fn border(&self) -> ([isize; 2], [isize; 2]) {
+        let (u, v) = rotate(self.pos);
+        let r = self.dist + 1;
+        ([u - r, u + r], [v - r, v + r])
     }
 }
 
-fn solve1(sensors: &Vec<Sensor>) {
-    let (mut min_x, mut max_x) = sensors
-        .iter()
-        .flat_map(|s| [s.pos.0, s.beacon.0])
-        .minmax()
-        .into_option()
-        .unwrap();
-    while sensors.iter().any(|s| s.in_reach((min_x, Y_TARGET))) {
-        min_x -= 10;
-    }
-    while sensors.iter().any(|s| s.in_reach((max_x, Y_TARGET))) {
-        max_x += 10;
-    }
-    let res = (min_x..=max_x)
-        .filter(|&x| {
-            !sensors.iter().any(|s| s.beacon == (x, Y_TARGET))
-                && sensors.iter().any(|s| s.in_reach((x, Y_TARGET)))
-        })
-        .count();
-    println!("Solution to problem 1: {res}");
+/// Disjoint, sorted intervals of x values covered on row `y`.
+fn row_coverage(sensors: &[Sensor], y: isize) -> Vec<Interval> {
+    let mut merged: Vec<Interval> = Vec::new();
+    for (start, end) in sensors.iter().flat_map(|s| s.row_interval(y)).sort() {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+fn find_beacon(sensors: &[Sensor], upper_bound: isize) -> Option<Pos> {
+    let (us, vs): (Vec<_>, Vec<_>) = sensors.iter().map(Sensor::border).unzip();
+    let us = us.into_iter().flatten().sort().dedup().collect_vec();
+    let vs = vs.into_iter().flatten().sort().dedup().collect_vec();
+    let in_bounds = |&(x, y): &Pos| (0..=upper_bound).contains(&x) && (0..=upper_bound).contains(&y);
+    // Besides the crossings of two border diagonals, the beacon may be wedged against the search area's edge.
+    let edges = [0, upper_bound];
+    let crossings = us.iter().cartesian_product(&vs).flat_map(|(&u, &v)| unrotate((u, v)));
+    let on_edges = us.iter().chain(&vs).cartesian_product(edges).flat_map(|(&d, e)| {
+        let from_u = [(e, d - e), (d - e, e)];
+        let from_v = [(e, e - d), (d + e, e)];
+        from_u.into_iter().chain(from_v)
+    });
+    let corners = edges.into_iter().cartesian_product(edges);
+    crossings
+        .chain(on_edges)
+        .chain(corners)
+        .filter(in_bounds)
+        .find(|&pos| !sensors.iter().any(|s| s.in_reach(pos)))
+}
+
+fn cell(sensors: &[Sensor], distress: Option<Pos>, pos: Pos) -> char {
+    if sensors.iter().any(|s| s.pos == pos) {
+        'S'
+    } else if sensors.iter().any(|s| s.beacon == pos) {
+        'B'
+    } else if distress == Some(pos) {
+        'X'
+    } else if sensors.iter().any(|s| s.in_reach(pos)) {
+        '#'
+    } else {
+        '.'
+    }
 }
 
-fn solve2(sensors: &Vec<Sensor>) {
-    for s in sensors {
-        for pos in s.border().filter(valid_pos) {
-            if sensors.iter().all(|s| !s.in_reach(pos)) {
-                println!("Solution to problem 2: {}", pos.0 * UPPER_BOUND + pos.1);
-                return;
+/// Draws the window in the puzzle's format, with the column numbers written vertically on top.
+fn render(sensors: &[Sensor], window: Window, distress: Option<Pos>) -> String {
+    let xs = window.min.0..=window.max.0;
+    let ys = window.min.1..=window.max.1;
+    let label_width = ys.clone().map(|y| y.to_string().len()).max().unwrap();
+    let col_labels = xs.clone().map(|x| (x % 5 == 0).then(|| x.to_string())).collect_vec();
+    let header_height = col_labels.iter().flatten().map(String::len).max().unwrap_or(0);
+    let mut res = String::new();
+    for line in 0..header_height {
+        res.push_str(&" ".repeat(label_width + 1));
+        for label in &col_labels {
+            let offset = header_height - label.as_ref().map_or(0, String::len);
+            let c = match label {
+                Some(label) if line >= offset => label.as_bytes()[line - offset] as char,
+                _ => ' ',
+            };
+            res.push(c);
+        }
+        res.push('\n');
+    }
+    for y in ys {
+        res.push_str(&format!("{y:>label_width$} "));
+        res.extend(xs.clone().map(|x| cell(sensors, distress, (x, y))));
+        res.push('\n');
+    }
+    res
+}
+
+/// Writes the window as a binary PPM, where every pixel covers a square block of cells. Coverage is
+/// sampled at the center of each block, and the sensor borders are drawn on top so they stay visible.
+fn write_ppm(
+    sensors: &[Sensor],
+    window: Window,
+    distress: Option<Pos>,
+    image_width: usize,
+    path: &PathBuf,
+) -> io::Result<()> {
+    let (width, height) = window.size();
+    let scale = width.div_ceil(image_width.max(1)).max(1);
+    let (w, h) = (width.div_ceil(scale), height.div_ceil(scale));
+    let to_pixel = |(x, y): Pos| {
+        let (px, py) = ((x - window.min.0) / scale as isize, (y - window.min.1) / scale as isize);
+        ((0..w as isize).contains(&px) && (0..h as isize).contains(&py)).then_some(py as usize * w + px as usize)
+    };
+    let mut pixels = vec![UNCOVERED_COLOR; w * h];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let offset = |p: usize| (p * scale + scale / 2) as isize;
+        let pos = (window.min.0 + offset(i % w), window.min.1 + offset(i / w));
+        if sensors.iter().any(|s| s.in_reach(pos)) {
+            *pixel = COVERED_COLOR;
+        }
+    }
+    for sensor in sensors {
+        let ([u0, u1], [v0, v1]) = sensor.border();
+        let corners = [(u0, v0), (u0, v1), (u1, v1), (u1, v0)].map(|c| unrotate(c).unwrap());
+        for (from, to) in corners.into_iter().circular_tuple_windows() {
+            let len = (to.0 - from.0).abs();
+            let dir = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+            let steps = (0..len).step_by(scale).chain([len]);
+            for pixel in steps.flat_map(|t| to_pixel((from.0 + dir.0 * t, from.1 + dir.1 * t))) {
+                pixels[pixel] = BORDER_COLOR;
             }
         }
     }
+    let markers = sensors
+        .iter()
+        .flat_map(|s| [(s.pos, SENSOR_COLOR), (s.beacon, BEACON_COLOR)])
+        .chain(distress.map(|d| (d, DISTRESS_COLOR)));
+    for (pos, color) in markers {
+        let Some(pixel) = to_pixel(pos) else { continue };
+        let (px, py) = ((pixel % w) as isize, (pixel / w) as isize);
+        for (dx, dy) in (-1..=1).cartesian_product(-1..=1) {
+            if (0..w as isize).contains(&(px + dx)) && (0..h as isize).contains(&(py + dy)) {
+                pixels[(py + dy) as usize * w + (px + dx) as usize] = color;
+            }
+        }
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P6\n{w} {h}\n255\n")?;
+    out.write_all(&pixels.concat())?;
+    out.flush()
+}
+
+fn solve1(sensors: &[Sensor], y_target: isize) {
+    let coverage = row_coverage(sensors, y_target);
+    let covered: isize = coverage.iter().map(|(start, end)| end - start + 1).sum();
+    let beacons = sensors
+        .iter()
+        .map(|s| s.beacon)
+        .filter(|&(x, y)| y == y_target && coverage.iter().any(|&(start, end)| (start..=end).contains(&x)))
+        .unique()
+        .count();
+    println!("Solution to problem 1: {}", covered - beacons as isize);
+}
+
+fn solve2(distress: Option<Pos>) {
+    match distress {
+        Some((x, y)) => println!("Solution to problem 2: {}", x * TUNING_FACTOR + y),
+        None => println!("Solution to problem 2: no distress beacon in range"),
+    }
 }
 
 fn main() {
-    let sensors = input!().lines().map(Sensor::parse).collect();
-    solve1(&sensors);
-    solve2(&sensors);
+    let args = Args::parse();
+    let (input, y_target, upper_bound) = match args.input {
+        InputType::Simple => (TEST.to_string(), TEST_Y_TARGET, TEST_UPPER_BOUND),
+        InputType::Long => (input!(), Y_TARGET, UPPER_BOUND),
+    };
+    let upper_bound = args.upper_bound.unwrap_or(upper_bound);
+    let sensors = input.lines().map(Sensor::parse).collect_vec();
+    solve1(&sensors, args.y_target.unwrap_or(y_target));
+    let distress = find_beacon(&sensors, upper_bound);
+    solve2(distress);
+    if let Some(window) = args.window {
+        print!("{}", render(&sensors, window, distress));
+    }
+    if let Some(path) = args.image {
+        let search_area = Window {
+            min: (0, 0),
+            max: (upper_bound, upper_bound),
+        };
+        write_ppm(&sensors, args.window.unwrap_or(search_area), distress, args.image_width, &path).unwrap();
+    }
 }
@@ -1,8 +1,11 @@
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    ops::{Index, IndexMut},
+    collections::{hash_map::Entry, HashMap},
+    fmt::{Display, Write},
+    ops::Index,
+    path::PathBuf,
 };
 
+use clap::{builder::RangedU64ValueParser, error::ErrorKind, CommandFactory, Parser, ValueEnum};
 use itertools::Itertools;
 use nom::{
     branch::alt,
@@ -16,20 +19,56 @@ use nom::{
 
 use utils::*;
 
+const START: &str = "AA";
+const MINUTES: u8 = 30;
+const TRAINING: u8 = 4;
+const ACTOR_COLORS: [&str; 5] = ["red", "blue", "darkgreen", "orange", "purple"];
+
 type Flow = i32;
-type Neighbors<'a> = HashMap<&'a str, Vec<(&'a str, u8)>>;
+type Mask = usize;
+type Route = Vec<(usize, u8)>;
+
+#[derive(Parser)]
+struct Args {
+    #[arg(long, default_value = START)]
+    start: String,
+    #[arg(long, default_value_t = MINUTES)]
+    minutes: u8,
+    #[arg(long, default_value_t = TRAINING)]
+    training: u8,
+    /// Number of actors cooperating in part 2
+    #[arg(long, default_value_t = 2, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    actors: usize,
+    /// Print which actor opens which valve at which minute
+    #[arg(long)]
+    schedule: bool,
+    /// Write the valve graph in Graphviz DOT format
+    #[arg(long)]
+    dot: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = DotKind::Tunnels)]
+    dot_kind: DotKind,
+    /// Highlight the optimal route of the given part in the DOT output
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=2))]
+    highlight: Option<u8>,
+}
+
+#[derive(Clone, ValueEnum)]
+enum DotKind {
+    /// Every valve and tunnel as given in the input
+    Tunnels,
+    /// Only the valves with flow, with travel times as edge weights
+    Compressed,
+}
 
-#[derive(Clone)]
 struct Valve<'a> {
     id: &'a str,
     flow: Flow,
-    opened: bool,
-    neighbors: Vec<(&'a str, u8)>,
+    neighbors: Vec<&'a str>,
 }
 
 impl Valve<'_> {
-    fn parse(s: &str) -> IResult<&str, Valve> {
-        let parse_id = take(2 as usize);
+    fn parse(s: &str) -> IResult<&str, Valve<'_>> {
+        let parse_id = take(2usize);
         let (s, id) = preceded(tag("Valve "), &parse_id)(s)?;
         let (s, flow_rate) = preceded(tag(" has flow rate="), i32)(s)?;
         let (s, neighbors) = alt((
@@ -45,187 +84,389 @@ impl Valve<'_> {
         let valve = Valve {
             id,
             flow: flow_rate,
-            neighbors: neighbors.into_iter().map(|n| (n, 1)).collect(),
-            opened: false,
+            neighbors,
         };
         Ok((s, valve))
     }
 }
 
 struct Graph<'a> {
-    valves: HashMap<&'a str, Valve<'a>>,
+    valves: Vec<Valve<'a>>,
+    indices: HashMap<&'a str, usize>,
 }
 
 impl<'a> Index<&str> for Graph<'a> {
     type Output = Valve<'a>;
     fn index(&self, index: &str) -> &Self::Output {
-        &self.valves[index]
+        &self.valves[self.indices[index]]
     }
 }
 
-impl<'a> IndexMut<&str> for Graph<'a> {
-    fn index_mut(&mut self, index: &str) -> &mut Self::Output {
-        self.valves.get_mut(index).unwrap()
+impl<'a> Graph<'a> {
+    fn new(valves: Vec<Valve<'a>>) -> Graph<'a> {
+        let indices = valves.iter().enumerate().map(|(i, v)| (v.id, i)).collect();
+        Graph { valves, indices }
     }
-}
 
-impl<'a> Graph<'a> {
-    fn new(valves: Vec<Valve>) -> Graph {
-        let valves = valves.into_iter().map(|v| (v.id, v)).collect();
-        Graph { valves }
-    }
-
-    fn reset(&mut self) {
-        self.valves.values_mut().for_each(|v| v.opened = false)
-    }
-
-    fn build_neighbor_matrix<'b>(&self) -> Neighbors<'b>
-    where
-        'a: 'b,
-    {
-        let mut res: Neighbors = HashMap::new();
-        for &id in self.valves.keys() {
-            let mut queue = VecDeque::from([(id, 0)]);
-            let mut visited = HashSet::new();
-            while let Some((id2, depth)) = queue.pop_front() {
-                if visited.contains(id2) {
-                    continue;
-                }
-                visited.insert(id2);
-                if id2 != id {
-                    res.entry(id).or_default().push((id2, depth));
-                }
-                for &(next, _) in &self[id2].neighbors {
-                    if next != id {
-                        queue.push_back((next, depth + 1));
+    /// All-pairs travel times by Floyd–Warshall, with `u8::MAX` for unreachable valves.
+    fn distances(&self) -> Vec<Vec<u8>> {
+        let n = self.valves.len();
+        let mut dist = vec![vec![u8::MAX; n]; n];
+        for (i, valve) in self.valves.iter().enumerate() {
+            dist[i][i] = 0;
+            for next in &valve.neighbors {
+                dist[i][self.indices[next]] = 1;
+            }
+        }
+        for k in 0..n {
+            for i in 0..n {
+                for j in 0..n {
+                    let through_k = dist[i][k].saturating_add(dist[k][j]);
+                    if through_k < dist[i][j] {
+                        dist[i][j] = through_k;
                     }
                 }
             }
         }
-        for valve in self.valves.values().filter(|v| v.flow == 0) {
-            if valve.id != "AA" {
-                res.remove(valve.id);
+        dist
+    }
+
+    fn to_dot(&self, schedule: Option<&Schedule>) -> String {
+        let dist = self.distances();
+        let mut opened = HashMap::new();
+        let mut walked = HashMap::new();
+        for (actor, route) in schedule.map(Schedule::routes).unwrap_or_default().into_iter().enumerate() {
+            let color = ACTOR_COLORS[actor % ACTOR_COLORS.len()];
+            for &[from, to] in route.array_windows() {
+                opened.insert(to, color);
+                // Retrace the shortest path between the two valves tunnel by tunnel.
+                let (mut cur, to) = (self.indices[from], self.indices[to]);
+                while cur != to {
+                    let next = self.valves[cur]
+                        .neighbors
+                        .iter()
+                        .map(|n| self.indices[n])
+                        .find(|&n| dist[n][to] < dist[cur][to])
+                        .unwrap();
+                    walked.insert((cur.min(next), cur.max(next)), color);
+                    cur = next;
+                }
+            }
+        }
+        let mut res = String::from("graph tunnels {\n");
+        for valve in &self.valves {
+            let label = format!("{}\\nflow={}", valve.id, valve.flow);
+            write_node(&mut res, valve.id, &label, valve.flow > 0, opened.get(valve.id));
+        }
+        for (i, valve) in self.valves.iter().enumerate() {
+            for j in valve.neighbors.iter().map(|n| self.indices[n]).filter(|&j| j > i) {
+                let (from, to) = (valve.id, self.valves[j].id);
+                write_edge(&mut res, from, to, None, walked.get(&(i, j)));
+            }
+        }
+        res.push_str("}\n");
+        res
+    }
+
+    /// Drops every valve without flow. The start position is appended as the last index.
+    fn compress(&self, start: &str) -> Network<'a> {
+        let dist = self.distances();
+        let mut kept = (0..self.valves.len())
+            .filter(|&i| self.valves[i].flow > 0)
+            .collect_vec();
+        kept.push(self.indices[start]);
+        Network {
+            ids: kept.iter().map(|&i| self.valves[i].id).collect(),
+            flows: kept.iter().map(|&i| self.valves[i].flow).collect(),
+            dist: kept.iter().map(|&i| kept.iter().map(|&j| dist[i][j]).collect()).collect(),
+            start: kept.len() - 1,
+        }
+    }
+}
+
+struct FlowProblem<'a> {
+    start: &'a str,
+    minutes: u8,
+    training: u8,
+    actors: usize,
+}
+
+impl FlowProblem<'_> {
+    fn solve<'a>(&self, graph: &Graph<'a>) -> Schedule<'a> {
+        let network = graph.compress(self.start);
+        let time = self.minutes.saturating_sub(self.training);
+        let routes = network.max_flow(time, self.actors);
+        let network = &network;
+        let mut openings = routes
+            .into_iter()
+            .enumerate()
+            .flat_map(|(actor, route)| {
+                route.into_iter().map(move |(valve, time_left)| Opening {
+                    actor,
+                    valve: network.ids[valve],
+                    minute: time - time_left,
+                    released: network.flows[valve] * time_left as Flow,
+                })
+            })
+            .collect_vec();
+        openings.sort_by_key(|o| (o.minute, o.actor));
+        Schedule {
+            start: network.ids[network.start],
+            openings,
+        }
+    }
+}
+
+struct Opening<'a> {
+    actor: usize,
+    valve: &'a str,
+    minute: u8,
+    released: Flow,
+}
+
+struct Schedule<'a> {
+    start: &'a str,
+    openings: Vec<Opening<'a>>,
+}
+
+impl<'a> Schedule<'a> {
+    fn total_flow(&self) -> Flow {
+        self.openings.iter().map(|o| o.released).sum()
+    }
+
+    /// The valves visited by each actor in order, beginning at the start valve.
+    fn routes(&self) -> Vec<Vec<&'a str>> {
+        let actors = self.openings.iter().map(|o| o.actor + 1).max().unwrap_or(0);
+        let mut routes = vec![vec![self.start]; actors];
+        for o in self.openings.iter().sort_by_key(|o| (o.actor, o.minute)) {
+            routes[o.actor].push(o.valve);
+        }
+        routes
+    }
+}
+
+impl Display for Schedule<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for o in &self.openings {
+            writeln!(
+                f,
+                "Minute {:>2}: actor {} opens valve {}, releasing {} pressure",
+                o.minute,
+                o.actor + 1,
+                o.valve,
+                o.released
+            )?;
+        }
+        write!(f, "Total pressure released: {}", self.total_flow())
+    }
+}
+
+/// The valves worth opening, indexed so that valve `i` corresponds to bit `i` of a `Mask`.
+struct Network<'a> {
+    ids: Vec<&'a str>,
+    flows: Vec<Flow>,
+    dist: Vec<Vec<u8>>,
+    start: usize,
+}
+
+impl Network<'_> {
+    fn num_valves(&self) -> usize {
+        self.start
+    }
+
+    fn to_dot(&self, schedule: Option<&Schedule>) -> String {
+        let mut opened = HashMap::new();
+        let mut walked = HashMap::new();
+        for (actor, route) in schedule.map(Schedule::routes).unwrap_or_default().into_iter().enumerate() {
+            let color = ACTOR_COLORS[actor % ACTOR_COLORS.len()];
+            for &[from, to] in route.array_windows() {
+                opened.insert(to, color);
+                walked.insert((from.min(to), from.max(to)), color);
+            }
+        }
+        // The start valve is only drawn separately if it has no flow of its own.
+        let nodes = (0..self.ids.len())
+            .filter(|&i| i < self.start || !self.ids[..self.start].contains(&self.ids[i]))
+            .collect_vec();
+        let mut res = String::from("graph compressed {\n");
+        for &i in &nodes {
+            let (id, flow) = (self.ids[i], self.flows[i]);
+            let label = format!("{id}\\nflow={flow}");
+            write_node(&mut res, id, &label, i != self.start, opened.get(id));
+        }
+        for (&i, &j) in nodes.iter().tuple_combinations() {
+            let (from, to) = (self.ids[i], self.ids[j]);
+            if self.dist[i][j] != u8::MAX {
+                let color = walked.get(&(from.min(to), from.max(to)));
+                write_edge(&mut res, from, to, Some(self.dist[i][j]), color);
             }
-            res.values_mut()
-                .for_each(|neighbors| neighbors.retain(|&(id, _)| id != valve.id));
         }
+        res.push_str("}\n");
         res
     }
 
-    fn max_flow(&mut self) -> Flow {
-        let neighbors = &self.build_neighbor_matrix();
-        self.max_flow_dfs("AA", 0, 29, neighbors)
+    /// The best total flow a single actor can release by opening exactly the valves in each mask, along
+    /// with the route achieving it as `(valve, minutes left after opening)` pairs.
+    fn best_per_subset(&self, time: u8) -> (Vec<Flow>, Vec<Route>) {
+        let mut best = vec![0; 1 << self.num_valves()];
+        let mut routes = vec![Vec::new(); best.len()];
+        let mut seen = HashMap::new();
+        let mut route = Vec::new();
+        self.explore(self.start, time, 0, 0, &mut route, &mut seen, &mut best, &mut routes);
+        (best, routes)
     }
 
-    fn max_flow_dfs(
-        &mut self,
-        from: &str,
-        current_flow: Flow,
+    #[allow(clippy::too_many_arguments)]
+    fn explore(
+        &self,
+        pos: usize,
         time: u8,
-        neighbors: &Neighbors,
-    ) -> Flow {
+        opened: Mask,
+        flow: Flow,
+        route: &mut Route,
+        seen: &mut HashMap<(usize, u8, Mask), Flow>,
+        best: &mut [Flow],
+        routes: &mut [Route],
+    ) {
+        match seen.entry((pos, time, opened)) {
+            Entry::Occupied(e) if *e.get() >= flow => return,
+            Entry::Occupied(mut e) => *e.get_mut() = flow,
+            Entry::Vacant(e) => {
+                e.insert(flow);
+            }
+        }
+        if flow > best[opened] {
+            best[opened] = flow;
+            routes[opened] = route.clone();
+        }
         if time == 0 {
-            return current_flow;
+            return;
         }
-        let mut max_flow = current_flow;
-        for &(next, dt) in &neighbors[from] {
-            if dt >= time || self[next].opened {
-                continue;
+        for next in (0..self.num_valves()).filter(|next| opened & (1 << next) == 0) {
+            let dt = self.dist[pos][next];
+            if dt < time - 1 {
+                let time = time - dt - 1;
+                let flow = flow + self.flows[next] * time as Flow;
+                route.push((next, time));
+                self.explore(next, time, opened | (1 << next), flow, route, seen, best, routes);
+                route.pop();
             }
-            self[next].opened = true;
-            let new_flow = self[next].flow * (time - dt) as Flow;
-            max_flow = max_flow.max(self.max_flow_dfs(
-                next,
-                current_flow + new_flow,
-                time - dt - 1,
-                neighbors,
-            ));
-            self[next].opened = false;
-        }
-        max_flow
-    }
-
-    fn max_flow2(&mut self) -> Flow {
-        let neighbors = self.build_neighbor_matrix();
-        self.max_flow2_dfs("AA", "AA", 0, 25, 25, &neighbors)
-    }
-
-    fn max_flow2_dfs(
-        &mut self,
-        from1: &str,
-        from2: &str,
-        current_flow: Flow,
-        t1: u8,
-        t2: u8,
-        neighbors: &Neighbors,
-    ) -> Flow {
-        if t1 == 0 && t2 == 0 {
-            current_flow
-        } else if t1 == 0 {
-            self.max_flow_dfs(from2, current_flow, t2, neighbors)
-        } else if t2 == 0 {
-            self.max_flow_dfs(from1, current_flow, t1, neighbors)
-        } else {
-            let mut max_flow = current_flow;
-            let has_next = |from: &str, t| {
-                neighbors[from]
-                    .iter()
-                    .any(|&(next, dt)| !self[next].opened && dt < t)
-            };
-            if !has_next(from1, t1) {
-                max_flow = max_flow.max(self.max_flow_dfs(from2, current_flow, t2, neighbors));
-            } else if !has_next(from2, t2) {
-                max_flow = max_flow.max(self.max_flow_dfs(from1, current_flow, t1, neighbors));
-            } else {
-                for &(next1, dt1) in &neighbors[from1] {
-                    if dt1 >= t1 || self[next1].opened {
-                        continue;
-                    }
-                    self[next1].opened = true;
-                    for &(next2, dt2) in &neighbors[from2] {
-                        if dt2 >= t2 || self[next2].opened {
-                            continue;
-                        }
-                        self[next2].opened = true;
-                        let new_flow = self[next1].flow * (t1 - dt1) as Flow
-                            + self[next2].flow * (t2 - dt2) as Flow;
-                        max_flow = max_flow.max(self.max_flow2_dfs(
-                            next1,
-                            next2,
-                            current_flow + new_flow,
-                            t1 - dt1 - 1,
-                            t2 - dt2 - 1,
-                            neighbors,
-                        ));
-                        self[next2].opened = false;
-                    }
-                    self[next1].opened = false;
+        }
+    }
+
+    /// The best routes for `actors` actors working in parallel, each opening a disjoint set of valves.
+    fn max_flow(&self, time: u8, actors: usize) -> Vec<Route> {
+        let (best, routes) = self.best_per_subset(time);
+        // team[mask] is the best flow using only valves in mask, and choice[mask] the subset opened
+        // by the last actor added to the team.
+        let mut team = best.clone();
+        let mut choice = (0..best.len()).collect_vec();
+        for bit in 0..self.num_valves() {
+            for mask in 0..team.len() {
+                if mask & (1 << bit) != 0 && team[mask ^ (1 << bit)] > team[mask] {
+                    team[mask] = team[mask ^ (1 << bit)];
+                    choice[mask] = choice[mask ^ (1 << bit)];
                 }
             }
-            max_flow
         }
+        let mut choices = vec![choice];
+        for _ in 1..actors {
+            let (next_team, choice) = (0..team.len())
+                .map(|mask| {
+                    let mut res = (team[mask], 0);
+                    let mut sub = mask;
+                    while sub != 0 {
+                        res = res.max((best[sub] + team[mask ^ sub], sub));
+                        sub = (sub - 1) & mask;
+                    }
+                    res
+                })
+                .unzip();
+            team = next_team;
+            choices.push(choice);
+        }
+        let mut mask = team.len() - 1;
+        let mut res = Vec::with_capacity(actors);
+        for choice in choices.iter().rev() {
+            let sub = choice[mask];
+            res.push(routes[sub].clone());
+            mask ^= sub;
+        }
+        res
     }
 }
 
-fn solve1(graph: &mut Graph) {
-    let max_flow = graph.max_flow();
-    println!("Solution to problem 1: {max_flow}");
+fn write_node(dot: &mut String, id: &str, label: &str, has_flow: bool, color: Option<&&str>) {
+    let shape = if has_flow { "ellipse" } else { "box" };
+    write!(dot, "    {id} [label=\"{label}\", shape={shape}").unwrap();
+    if let Some(color) = color {
+        write!(dot, ", style=filled, fillcolor={color}").unwrap();
+    }
+    dot.push_str("];\n");
 }
 
-fn solve2(mut graph: Graph) {
-    let max_flow = graph.max_flow2();
-    println!("Solution to problem 2: {max_flow}");
+fn write_edge(dot: &mut String, from: &str, to: &str, weight: Option<u8>, color: Option<&&str>) {
+    write!(dot, "    {from} -- {to} [").unwrap();
+    if let Some(weight) = weight {
+        write!(dot, "label=\"{weight}\", ").unwrap();
+    }
+    match color {
+        Some(color) => write!(dot, "color={color}, penwidth=3").unwrap(),
+        None => write!(dot, "color=gray").unwrap(),
+    }
+    dot.push_str("];\n");
+}
+
+fn solve1<'a>(graph: &Graph<'a>, args: &Args) -> Schedule<'a> {
+    let problem = FlowProblem {
+        start: &args.start,
+        minutes: args.minutes,
+        training: 0,
+        actors: 1,
+    };
+    let schedule = problem.solve(graph);
+    println!("Solution to problem 1: {}", schedule.total_flow());
+    if args.schedule {
+        println!("{schedule}");
+    }
+    schedule
+}
+
+fn solve2<'a>(graph: &Graph<'a>, args: &Args) -> Schedule<'a> {
+    let problem = FlowProblem {
+        start: &args.start,
+        minutes: args.minutes,
+        training: args.training,
+        actors: args.actors,
+    };
+    let schedule = problem.solve(graph);
+    println!("Solution to problem 2: {}", schedule.total_flow());
+    if args.schedule {
+        println!("{schedule}");
+    }
+    schedule
 }
 
 fn main() {
+    let args = Args::parse();
     let input = input!();
     let valves = input
         .lines()
         .flat_map(Valve::parse)
         .map(|t| t.1)
         .collect_vec();
-    let mut graph = Graph::new(valves);
-    solve1(&mut graph);
-    graph.reset();
-    solve2(graph);
+    let graph = Graph::new(valves);
+    if !graph.indices.contains_key(args.start.as_str()) {
+        let msg = format!("there is no valve {} to start from", args.start);
+        Args::command().error(ErrorKind::InvalidValue, msg).exit();
+    }
+    let schedules = [solve1(&graph, &args), solve2(&graph, &args)];
+    if let Some(path) = &args.dot {
+        let schedule = args.highlight.map(|part| &schedules[part as usize - 1]);
+        let dot = match args.dot_kind {
+            DotKind::Tunnels => graph.to_dot(schedule),
+            DotKind::Compressed => graph.compress(&args.start).to_dot(schedule),
+        };
+        std::fs::write(path, dot).unwrap();
+    }
 }
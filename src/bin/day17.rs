@@ -1,6 +1,14 @@
-use std::{iter::Cycle, ops::Range, slice::Iter};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
-use itertools::Itertools;
+use clap::{value_parser, Parser, ValueEnum};
+use termion::{cursor::Goto, cursor::HideCursor, event::Key, input::TermRead, raw::IntoRawMode};
 use utils::*;
 
 use Direction::*;
@@ -21,164 +29,488 @@ impl From<char> for Direction {
     }
 }
 
-type Pos = (usize, usize);
-type Shape = Vec<Pos>;
+/// One chamber row, with bit `x` set if column `x` is occupied.
+type Row = u64;
 
+const WIDTH: usize = 7;
+const SPAWN_X: usize = 2;
+const SPAWN_GAP: usize = 3;
+const PROFILE_ROWS: usize = 1024;
+const MIN_PRUNE_ROWS: usize = 64;
+const MAX_CYCLE_SEARCH: usize = 100_000;
+const NUM_ROCKS: usize = 2022;
 const MAX_NUM: usize = 1_000_000_000_000;
-const GRID_SIZE: usize = 1_000;
+const FPS: u64 = 10;
+const MAX_FPS: u64 = 1000;
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+const TEST: &str = ">>><<><>><<<>><>>><<<>>><<<><<<>><>><<>>";
+
+const CONTROLS: &str = "space: pause  s: step  n: next rock  +/-: speed  q: quit";
+
+const SHAPES: &str = "####
+
+.#.
+###
+.#.
+
+..#
+..#
+###
+
+#
+#
+#
+#
+
+##
+##";
+
+#[derive(Parser)]
+struct Args {
+    #[arg(value_enum, default_value_t = InputType::Long)]
+    input: InputType,
+    /// Number of rocks to drop in part 2
+    #[arg(long, default_value_t = MAX_NUM)]
+    rocks: usize,
+    /// Rock shapes drawn with `#` and `.`, separated by blank lines
+    #[arg(long)]
+    shapes: Option<PathBuf>,
+    #[arg(long, default_value_t = WIDTH)]
+    width: usize,
+    /// Print every push and fall of the first N rocks, like the puzzle's example
+    #[arg(long)]
+    trace: Option<usize>,
+    /// Animate the falling rocks in the terminal
+    #[arg(long)]
+    animate: bool,
+    #[arg(long, default_value_t = FPS, value_parser = value_parser!(u64).range(1..))]
+    fps: u64,
+    /// Drop this many rocks before the animation starts
+    #[arg(long, default_value_t = 0)]
+    jump: usize,
+}
+
+#[derive(Clone, ValueEnum)]
+enum InputType {
+    Simple,
+    Long,
+}
+
+#[derive(Clone, Copy)]
+enum Step {
+    Spawned,
+    Pushed(Direction, bool),
+    Fell,
+    Rested,
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Spawned => write!(f, "A new rock begins falling:"),
+            Step::Pushed(dir, moved) => {
+                let dir = if *dir == Left { "left" } else { "right" };
+                let suffix = if *moved { "" } else { ", but nothing happens" };
+                write!(f, "Jet of gas pushes rock {dir}{suffix}:")
+            }
+            Step::Fell => write!(f, "Rock falls 1 unit:"),
+            Step::Rested => write!(f, "Rock falls 1 unit, causing it to come to rest:"),
+        }
+    }
+}
+
+/// What the next rocks will fall into: the next jet, the next rock and a hash of the reachable squares
+/// of each row down to the last reachable one, but at most `PROFILE_ROWS`. Some rule sets leave a shaft
+/// open all the way down, so this is only a candidate for a repeat, which `height_after` confirms
+/// before relying on it.
+type State = (usize, usize, u64);
+
+/// The chamber width and the rock shapes, each stored bottom row first with its left edge in column 0.
+struct Rules {
+    width: usize,
+    shapes: Vec<Vec<Row>>,
+}
+
+impl Rules {
+    fn parse(s: &str, width: usize) -> Result<Rules, String> {
+        if !(SPAWN_X + 1..=Row::BITS as usize).contains(&width) {
+            return Err(format!("chamber width must be between {} and {}", SPAWN_X + 1, Row::BITS));
+        }
+        let shapes: Vec<Vec<Row>> = s
+            .trim()
+            .split("\n\n")
+            .map(|shape| {
+                shape
+                    .lines()
+                    .rev()
+                    .map(|line| {
+                        line.trim().chars().enumerate().try_fold(0, |row, (x, c)| match c {
+                            '#' if SPAWN_X + x < width => Ok(row | 1 << x),
+                            '#' => Err(format!("rock does not fit in a chamber of width {width}:\n{shape}")),
+                            '.' => Ok(row),
+                            _ => Err(format!("invalid character {c:?} in rock shape")),
+                        })
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, _>>()?;
+        if shapes.iter().any(|shape| shape.iter().all(|&row| row == 0)) {
+            return Err("every rock shape needs at least one `#`".to_string());
+        }
+        Ok(Rules { width, shapes })
+    }
+
+    fn full(&self) -> Row {
+        Row::MAX >> (Row::BITS as usize - self.width)
+    }
+}
+
+#[derive(Clone)]
+struct Rock {
+    rows: Vec<Row>,
+    y: usize,
+}
+
+impl Rock {
+    fn contains(&self, (x, y): (usize, usize)) -> bool {
+        y >= self.y && self.rows.get(y - self.y).is_some_and(|row| row & (1 << x) != 0)
+    }
+}
 
 struct Simulator<'a> {
-    input: Cycle<Iter<'a, Direction>>,
-    generator: Cycle<Range<u8>>,
-    grid: [[bool; 7]; GRID_SIZE],
-    highest_points: [usize; 7],
+    rules: &'a Rules,
+    input: &'a [Direction],
+    jet: usize,
+    rock: usize,
+    rows: VecDeque<Row>,
     base: usize,
-    num_resizes: usize,
+    prune_at: usize,
+    dropped: usize,
+    falling: Option<Rock>,
+    pushed: bool,
 }
 
-impl Simulator<'_> {
-    fn new(input: &Vec<Direction>) -> Simulator {
+impl<'a> Simulator<'a> {
+    fn new(input: &'a [Direction], rules: &'a Rules) -> Simulator<'a> {
         Simulator {
-            input: input.iter().cycle(),
-            generator: (0..5).cycle(),
-            grid: [[false; 7]; GRID_SIZE].into(),
-            highest_points: [0; 7],
+            rules,
+            input,
+            jet: 0,
+            rock: 0,
+            rows: VecDeque::new(),
             base: 0,
-            num_resizes: 0,
+            prune_at: MIN_PRUNE_ROWS,
+            dropped: 0,
+            falling: None,
+            pushed: false,
         }
     }
 
-    fn highest_point(&self) -> usize {
-        *self.highest_points.iter().max().unwrap()
+    fn height(&self) -> usize {
+        self.base + self.rows.len()
     }
 
-    fn highest_point_absolute(&self) -> usize {
-        self.highest_point() + self.base
+    /// Pruned rows count as full, since no rock can reach them anyway.
+    fn row(&self, y: usize) -> Row {
+        match y.checked_sub(self.base) {
+            Some(i) => self.rows.get(i).copied().unwrap_or(0),
+            None => self.rules.full(),
+        }
     }
 
-    fn resize(&mut self) {
-        self.base += GRID_SIZE / 2;
-        self.grid.rotate_left(self.base);
-        for row in &mut self.grid[self.base..] {
-            *row = [false; 7];
-        }
-        for y in &mut self.highest_points {
-            assert!(*y >= self.base);
-            *y -= self.base
-        }
-        self.num_resizes += 1;
+    fn collides(&self, rows: impl Iterator<Item = Row>, y: usize) -> bool {
+        rows.enumerate().any(|(i, row)| row & self.row(y + i) != 0)
     }
 
-    fn initial_y(&mut self) -> usize {
-        let mut y = self.highest_point() + 4;
-        if y >= self.grid.len() - 10 {
-            self.resize();
-            y -= GRID_SIZE / 2;
+    fn state(&self) -> State {
+        let full = self.rules.full();
+        let mut reachable = full;
+        let mut hasher = DefaultHasher::new();
+        for y in (0..self.height()).rev().take(PROFILE_ROWS) {
+            let free = !self.row(y) & full;
+            reachable = flood(reachable & free, free);
+            if reachable == 0 {
+                break;
+            }
+            reachable.hash(&mut hasher);
         }
-        y
+        (self.jet, self.rock, hasher.finish())
     }
 
-    fn spawn(&mut self) -> Shape {
-        let y = self.initial_y();
-        let shape = match self.generator.pop() {
-            0 => (2..=5).map(|x| (x, y)).collect(),
-            1 => {
-                vec![(3, y), (3, y + 1), (3, y + 2), (2, y + 1), (4, y + 1)]
-            }
-            2 => {
-                vec![(2, y), (3, y), (4, y), (4, y + 1), (4, y + 2)]
-            }
-            3 => (y..y + 4).map(|y| (2, y)).collect(),
-            _ => {
-                vec![(2, y), (3, y), (2, y + 1), (3, y + 1)]
-            }
-        };
-        shape
+    fn spawn(&mut self) -> Rock {
+        let shape = &self.rules.shapes[self.rock];
+        self.rock = (self.rock + 1) % self.rules.shapes.len();
+        Rock {
+            rows: shape.iter().map(|row| row << SPAWN_X).collect(),
+            y: self.height() + SPAWN_GAP,
+        }
     }
 
-    fn fall(&mut self, shape: &mut Shape) -> bool {
-        if shape.iter().any(|p| p.1 == 1 || self.grid[p.1 - 1][p.0]) {
+    fn fall(&self, rock: &mut Rock) -> bool {
+        if rock.y == 0 || self.collides(rock.rows.iter().copied(), rock.y - 1) {
             false
         } else {
-            shape.iter_mut().for_each(|p| p.1 -= 1);
+            rock.y -= 1;
             true
         }
     }
 
-    fn push(&mut self, shape: &mut Shape, dir: Direction) {
-        match dir {
-            Left => {
-                if !shape.iter().any(|p| p.0 == 0 || self.grid[p.1][p.0 - 1]) {
-                    shape.iter_mut().for_each(|p| p.0 -= 1);
-                }
-            }
-            Right => {
-                if !shape.iter().any(|p| p.0 == 6 || self.grid[p.1][p.0 + 1]) {
-                    shape.iter_mut().for_each(|p| p.0 += 1);
-                }
-            }
+    fn push(&self, rock: &mut Rock, dir: Direction) -> bool {
+        let wall = match dir {
+            Left => 1,
+            Right => 1 << (self.rules.width - 1),
+        };
+        let shift = |row: Row| match dir {
+            Left => row >> 1,
+            Right => row << 1,
+        };
+        let free = rock.rows.iter().all(|row| row & wall == 0)
+            && !self.collides(rock.rows.iter().map(|&row| shift(row)), rock.y);
+        if free {
+            rock.rows.iter_mut().for_each(|row| *row = shift(*row));
         }
+        free
     }
 
-    fn sim1(&mut self) {
-        let mut shape = self.spawn();
-        while let Some(&dir) = self.input.next() {
-            self.push(&mut shape, dir);
-            if !self.fall(&mut shape) {
-                break;
+    fn place(&mut self, rock: Rock) {
+        for (i, row) in rock.rows.into_iter().enumerate().filter(|&(_, row)| row != 0) {
+            let y = rock.y + i;
+            while self.height() <= y {
+                self.rows.push_back(0);
             }
+            self.rows[y - self.base] |= row;
         }
-        for (x, y) in shape {
-            self.grid[y][x] = true;
-            self.highest_points[x] = self.highest_points[x].max(y);
+        self.dropped += 1;
+        // Pruning scans the whole reachable region, which need not be shallow, so it waits until the
+        // rows have doubled since last time.
+        if self.rows.len() >= self.prune_at {
+            self.prune();
+            self.prune_at = (2 * self.rows.len()).max(MIN_PRUNE_ROWS);
         }
     }
 
+    /// Drops the rows below the lowest cell that is still reachable from above. The lowest column top
+    /// is not a safe cut, as rocks can slide in under overhangs.
+    fn prune(&mut self) {
+        let full = self.rules.full();
+        let mut reachable = full;
+        for i in (0..self.rows.len()).rev() {
+            let free = !self.rows[i] & full;
+            reachable = flood(reachable & free, free);
+            if reachable == 0 {
+                // Row i is still needed to stop rocks falling into the row above it.
+                self.rows.drain(..i);
+                self.base += i;
+                return;
+            }
+        }
+    }
+
+    /// Advances the simulation by a single spawn, push or fall.
+    fn step(&mut self) -> Step {
+        let Some(mut rock) = self.falling.take() else {
+            self.falling = Some(self.spawn());
+            return Step::Spawned;
+        };
+        let step = if !self.pushed {
+            let dir = self.input[self.jet];
+            self.jet = (self.jet + 1) % self.input.len();
+            Step::Pushed(dir, self.push(&mut rock, dir))
+        } else if self.fall(&mut rock) {
+            Step::Fell
+        } else {
+            self.place(rock);
+            self.falling = None;
+            self.pushed = false;
+            return Step::Rested;
+        };
+        self.falling = Some(rock);
+        self.pushed = !self.pushed;
+        step
+    }
+
+    fn sim1(&mut self) {
+        while !matches!(self.step(), Step::Rested) {}
+    }
+
     fn sim(&mut self, n: usize) -> usize {
         for _ in 0..n {
             self.sim1();
         }
-        self.highest_point_absolute()
+        self.height()
     }
 }
 
-const CHECK_LIM: usize = 5;
+/// Spreads `reachable` sideways through the `free` squares of a row.
+fn flood(mut reachable: Row, free: Row) -> Row {
+    loop {
+        let spread = (reachable | reachable << 1 | reachable >> 1) & free;
+        if spread == reachable {
+            return reachable;
+        }
+        reachable = spread;
+    }
+}
+
+impl Display for Simulator<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let top = self.falling.as_ref().map_or(0, |r| r.y + r.rows.len()).max(self.height());
+        for y in (self.base..top).rev() {
+            let row: String = (0..self.rules.width)
+                .map(|x| match &self.falling {
+                    Some(rock) if rock.contains((x, y)) => '@',
+                    _ if self.row(y) & (1 << x) != 0 => '#',
+                    _ => '.',
+                })
+                .collect();
+            writeln!(f, "|{row}|")?;
+        }
+        // Pruned rows can no longer be reached, so they are drawn as a single jagged line.
+        let bottom = if self.base == 0 { "-" } else { "~" };
+        write!(f, "+{}+", bottom.repeat(self.rules.width))
+    }
+}
+
+/// The tower height after `n` rocks. Once a state repeats, the rocks dropped since its first
+/// occurrence are a candidate cycle, which is confirmed once the next as many rocks have added the same
+/// heights one by one. All remaining full cycles are then skipped.
+fn height_after(input: &[Direction], rules: &Rules, n: usize) -> Result<usize, String> {
+    let mut s = Simulator::new(input, rules);
+    let mut seen: HashMap<State, usize> = HashMap::new();
+    let mut heights = vec![0];
+    let mut candidate = None;
+    for dropped in 0..n {
+        if let Some((start, period)) = candidate {
+            if dropped == start + period {
+                let gain = |i: usize| heights[i + 1] - heights[i];
+                if (start..dropped).all(|i| gain(i) == gain(i - period)) {
+                    let remainder = (n - dropped) % period;
+                    let extra = heights[start + remainder] - heights[start];
+                    return Ok(s.height() + (n - dropped) / period * (s.height() - heights[start]) + extra);
+                }
+                candidate = None;
+            }
+        }
+        if let Some(prev) = seen.insert(s.state(), dropped) {
+            candidate = candidate.or(Some((dropped, dropped - prev)));
+        }
+        if dropped == MAX_CYCLE_SEARCH {
+            return Err(format!("the tower does not repeat within {MAX_CYCLE_SEARCH} rocks"));
+        }
+        s.sim1();
+        heights.push(s.height());
+    }
+    Ok(s.height())
+}
 
-fn solve1(input: &Vec<Direction>) {
-    let mut s1 = Simulator::new(&input);
-    let res = s1.sim(2022);
+fn solve1(input: &[Direction], rules: &Rules) {
+    let res = height_after(input, rules, NUM_ROCKS).unwrap_or_else(|e| fail(&e));
     println!("Solution to problem 1: {res}");
 }
 
-fn solve2(input: &Vec<Direction>) {
-    let cycle = match input.len() % 5 {
-        0 => input.len(),
-        _ => input.len() * 5
-    };
-    let mut cache = vec![0];
-    let mut s = Simulator::new(input);
-    for k in 1.. {
-        let res = s.sim(cycle);
-        cache.push(res);
-        if k % CHECK_LIM == 0
-            && (0..CHECK_LIM)
-                .map(|i| cache[(i + 1) * k / CHECK_LIM] - cache[i * k / CHECK_LIM])
-                .all_equal()
-        {
-            let cycle = cycle * k / CHECK_LIM;
-            let cycle_height = cache[k / CHECK_LIM];
-            let num_cycles = MAX_NUM / cycle;
-            let mut s = Simulator::new(input);
-            let remainder_height = s.sim(MAX_NUM % cycle);
-            println!("Solution to problem 2: {}", cycle_height * num_cycles + remainder_height);
-            return;
-        }
-    }
-    unreachable!()
+fn solve2(input: &[Direction], rules: &Rules, rocks: usize) {
+    let res = height_after(input, rules, rocks).unwrap_or_else(|e| fail(&e));
+    println!("Solution to problem 2: {res}");
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(1)
+}
+
+fn trace(input: &[Direction], rules: &Rules, n: usize) {
+    let mut s = Simulator::new(input, rules);
+    while s.dropped < n {
+        let step = s.step();
+        println!("{step}\n{s}\n");
+    }
+}
+
+fn animate(input: &[Direction], rules: &Rules, jump: usize, mut fps: u64) {
+    let mut s = Simulator::new(input, rules);
+    s.sim(jump);
+    let mut stdout = HideCursor::from(io::stdout().into_raw_mode().unwrap());
+    let mut keys = termion::async_stdin().keys();
+    let mut paused = false;
+    let mut last_step = s.step();
+    let mut last_tick = Instant::now();
+    let mut redraw = true;
+    'anim: loop {
+        let mut steps = 0;
+        for key in keys.by_ref().flatten() {
+            match key {
+                Key::Char('q') | Key::Esc | Key::Ctrl('c') => break 'anim,
+                Key::Char(' ') | Key::Char('p') => paused = !paused,
+                Key::Char('s') | Key::Char('.') => {
+                    paused = true;
+                    steps += 1;
+                }
+                Key::Char('n') => {
+                    paused = true;
+                    let target = s.dropped + 1;
+                    while s.dropped < target {
+                        last_step = s.step();
+                    }
+                }
+                Key::Char('+') | Key::Char('=') => fps = (fps * 2).min(MAX_FPS),
+                Key::Char('-') => fps = (fps / 2).max(1),
+                _ => continue,
+            }
+            redraw = true;
+        }
+        if !paused && last_tick.elapsed() >= Duration::from_millis(1000 / fps) {
+            steps += 1;
+        }
+        if steps > 0 {
+            for _ in 0..steps {
+                last_step = s.step();
+            }
+            last_tick = Instant::now();
+            redraw = true;
+        }
+        if redraw {
+            let (cols, rows) = termion::terminal_size().unwrap_or((80, 24));
+            let state = if paused { "paused" } else { "running" };
+            let status = format!(
+                "rock {} | height {} | fps: {fps} | {state} | {last_step} | {CONTROLS}",
+                s.dropped + 1,
+                s.height()
+            );
+            let status: String = status.chars().take(cols as usize).collect();
+            write!(stdout, "{}{}", termion::clear::All, Goto(1, 1)).unwrap();
+            for line in s.to_string().lines().take(rows.saturating_sub(1) as usize) {
+                write!(stdout, "{line}\r\n").unwrap();
+            }
+            write!(stdout, "{}{status}", Goto(1, rows)).unwrap();
+            stdout.flush().unwrap();
+            redraw = false;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    write!(stdout, "{}{}{}", termion::clear::All, Goto(1, 1), termion::cursor::Show).unwrap();
+    stdout.flush().unwrap();
 }
 
 fn main() {
-    let input = input!().chars().map(Direction::from).collect();
-    solve1(&input);
-    solve2(&input);
+    let args = Args::parse();
+    let input = match args.input {
+        InputType::Simple => TEST.to_string(),
+        InputType::Long => input!(),
+    };
+    let input: Vec<_> = input.trim().chars().map(Direction::from).collect();
+    let shapes = match &args.shapes {
+        Some(path) => std::fs::read_to_string(path).unwrap(),
+        None => SHAPES.to_string(),
+    };
+    let rules = Rules::parse(&shapes, args.width).unwrap_or_else(|e| panic!("{e}"));
+    if args.animate {
+        animate(&input, &rules, args.jump, args.fps);
+        return;
+    }
+    if let Some(n) = args.trace {
+        trace(&input, &rules, n);
+    }
+    solve1(&input, &rules);
+    solve2(&input, &rules, args.rocks);
 }
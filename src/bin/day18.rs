@@ -1,122 +1,381 @@
 use std::{
-    collections::{HashSet, VecDeque},
-    ops::{Add, Index, IndexMut},
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
 };
 
+use clap::Parser;
+use itertools::Itertools;
 use utils::*;
-use Particle::*;
-
-const UNITS: [Pos; 6] = [
-    Pos::new(1, 0, 0),
-    Pos::new(0, 1, 0),
-    Pos::new(0, 0, 1),
-    Pos::new(-1, 0, 0),
-    Pos::new(0, -1, 0),
-    Pos::new(0, 0, -1),
-];
-
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
-struct Pos {
-    x: isize,
-    y: isize,
-    z: isize,
+use voxel::*;
+
+#[derive(Parser)]
+struct Args {
+    /// Write the exposed faces as a Wavefront OBJ mesh, with a material library next to it
+    #[arg(long)]
+    obj: Option<PathBuf>,
+    /// Write the exposed faces as an ASCII STL mesh
+    #[arg(long)]
+    stl: Option<PathBuf>,
 }
 
-impl Add<Pos> for Pos {
-    type Output = Pos;
-    fn add(self, rhs: Pos) -> Self::Output {
-        Pos {
-            x: self.x + rhs.x,
-            y: self.y + rhs.y,
-            z: self.z + rhs.z,
+mod voxel {
+    use std::{
+        collections::{HashSet, VecDeque},
+        ops::Add,
+    };
+
+    pub const UNITS: [Pos; 6] = [
+        Pos::new(1, 0, 0),
+        Pos::new(0, 1, 0),
+        Pos::new(0, 0, 1),
+        Pos::new(-1, 0, 0),
+        Pos::new(0, -1, 0),
+        Pos::new(0, 0, -1),
+    ];
+
+    #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+    pub struct Pos {
+        pub x: isize,
+        pub y: isize,
+        pub z: isize,
+    }
+
+    impl Add<Pos> for Pos {
+        type Output = Pos;
+        fn add(self, rhs: Pos) -> Self::Output {
+            Pos {
+                x: self.x + rhs.x,
+                y: self.y + rhs.y,
+                z: self.z + rhs.z,
+            }
         }
     }
-}
 
-impl Pos {
-    const fn new(x: isize, y: isize, z: isize) -> Pos {
-        Pos { x, y, z }
+    impl Pos {
+        pub const fn new(x: isize, y: isize, z: isize) -> Pos {
+            Pos { x, y, z }
+        }
+
+        pub fn around(&self) -> impl Iterator<Item = Pos> + '_ {
+            UNITS.iter().map(|&u| u + *self)
+        }
+
+        fn cross(self, other: Pos) -> Pos {
+            Pos::new(
+                self.y * other.z - self.z * other.y,
+                self.z * other.x - self.x * other.z,
+                self.x * other.y - self.y * other.x,
+            )
+        }
+
+        fn zip_with(self, other: Pos, f: fn(isize, isize) -> isize) -> Pos {
+            Pos::new(f(self.x, other.x), f(self.y, other.y), f(self.z, other.z))
+        }
+    }
+
+    /// An inclusive axis-aligned box.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Bounds {
+        pub min: Pos,
+        pub max: Pos,
     }
 
-    fn parse(s: &str) -> Pos {
-        let (x, y, z) = s.split(',').flat_map(str::parse).pop3();
-        Pos { x, y, z }
+    impl Bounds {
+        fn point(p: Pos) -> Bounds {
+            Bounds { min: p, max: p }
+        }
+
+        fn union(self, other: Bounds) -> Bounds {
+            Bounds {
+                min: self.min.zip_with(other.min, isize::min),
+                max: self.max.zip_with(other.max, isize::max),
+            }
+        }
+
+        fn expand(self, n: isize) -> Bounds {
+            Bounds {
+                min: self.min + Pos::new(-n, -n, -n),
+                max: self.max + Pos::new(n, n, n),
+            }
+        }
+
+        fn overlaps(&self, other: &Bounds) -> bool {
+            self.min.x <= other.max.x
+                && other.min.x <= self.max.x
+                && self.min.y <= other.max.y
+                && other.min.y <= self.max.y
+                && self.min.z <= other.max.z
+                && other.min.z <= self.max.z
+        }
+
+        fn contains(&self, p: Pos) -> bool {
+            self.overlaps(&Bounds::point(p))
+        }
+
+        fn size(&self) -> (usize, usize, usize) {
+            let d = |min: isize, max: isize| (max - min + 1) as usize;
+            (d(self.min.x, self.max.x), d(self.min.y, self.max.y), d(self.min.z, self.max.z))
+        }
+
+        fn volume(&self) -> usize {
+            let (w, h, d) = self.size();
+            w * h * d
+        }
+
+        fn index(&self, p: Pos) -> usize {
+            let (w, h, _) = self.size();
+            let (x, y, z) = (p.x - self.min.x, p.y - self.min.y, p.z - self.min.z);
+            (z as usize * h + y as usize) * w + x as usize
+        }
+
+        fn pos(&self, i: usize) -> Pos {
+            let (w, h, _) = self.size();
+            self.min + Pos::new((i % w) as isize, (i / w % h) as isize, (i / (w * h)) as isize)
+        }
     }
 
-    fn around(&self) -> impl Iterator<Item = Pos> + '_ {
-        UNITS.iter().map(|&u| u + *self)
+    #[derive(PartialEq, Eq, Clone, Copy)]
+    enum Particle {
+        Lava,
+        Air,
+        Steam,
+        Pocket(usize),
     }
 
-    fn is_clamped(&self, max_x: isize, max_y: isize, max_z: isize) -> bool {
-        self.x >= 0
-            && self.y >= 0
-            && self.z >= 0
-            && self.x < max_x
-            && self.y < max_y
-            && self.z < max_z
+    pub struct Pocket {
+        pub volume: usize,
+    }
+
+    /// The side of `cube` facing `normal`, exposed either to the outside or to the given pocket.
+    pub struct Face {
+        pub cube: Pos,
+        pub normal: Pos,
+        pub pocket: Option<usize>,
+    }
+
+    impl Face {
+        /// The corners of the unit square, counter-clockwise when seen from outside the cube.
+        pub fn corners(&self) -> [Pos; 4] {
+            let n = self.normal;
+            let (u, v) = match UNITS.iter().position(|&unit| unit == n).unwrap() % 3 {
+                0 => (UNITS[1], UNITS[2]),
+                1 => (UNITS[2], UNITS[0]),
+                _ => (UNITS[0], UNITS[1]),
+            };
+            let (u, v) = if u.cross(v) == n { (u, v) } else { (v, u) };
+            let origin = self.cube + n.zip_with(Pos::new(0, 0, 0), isize::max);
+            [origin, origin + u, origin + u + v, origin + v]
+        }
+    }
+
+    pub struct Surface {
+        pub exterior: usize,
+        pub interior: usize,
+        pub pockets: Vec<Pocket>,
+        pub faces: Vec<Face>,
+    }
+
+    pub struct Voxels {
+        cubes: HashSet<Pos>,
+    }
+
+    impl Voxels {
+        pub fn new(cubes: impl IntoIterator<Item = Pos>) -> Voxels {
+            Voxels {
+                cubes: cubes.into_iter().collect(),
+            }
+        }
+
+        /// Every cube face not touching another cube, whether it faces outwards or into a pocket.
+        pub fn surface_area(&self) -> usize {
+            self.cubes
+                .iter()
+                .flat_map(Pos::around)
+                .filter(|p| !self.cubes.contains(p))
+                .count()
+        }
+
+        /// Splits the cubes into groups whose bounding boxes, padded by one, are disjoint. Air enclosed by
+        /// the droplet never leaves the box of its group, so far-apart groups can be searched separately.
+        fn groups(&self) -> Vec<(Bounds, Vec<Pos>)> {
+            let mut groups: Vec<(Bounds, Vec<Pos>)> = Vec::new();
+            for &cube in &self.cubes {
+                let mut group = (Bounds::point(cube), vec![cube]);
+                // Merging can grow the box into groups that were disjoint before, so repeat until stable.
+                while let Some(i) = groups.iter().position(|(b, _)| b.expand(1).overlaps(&group.0)) {
+                    let (bounds, cubes) = groups.swap_remove(i);
+                    group.0 = group.0.union(bounds);
+                    group.1.extend(cubes);
+                }
+                groups.push(group);
+            }
+            groups
+        }
+
+        /// Fills the air reachable from `from` with `fill`, collecting the lava faces it touches. Returns
+        /// the number of cells filled.
+        fn flood(
+            space: &mut [Particle],
+            bounds: &Bounds,
+            from: Pos,
+            fill: Particle,
+            faces: &mut Vec<Face>,
+        ) -> usize {
+            let pocket = match fill {
+                Particle::Pocket(id) => Some(id),
+                _ => None,
+            };
+            let mut volume = 0;
+            space[bounds.index(from)] = fill;
+            let mut queue = VecDeque::from([from]);
+            while let Some(p) = queue.pop_front() {
+                volume += 1;
+                for (&unit, next) in UNITS.iter().zip(p.around()).filter(|&(_, n)| bounds.contains(n)) {
+                    let cell = &mut space[bounds.index(next)];
+                    match *cell {
+                        Particle::Lava => faces.push(Face {
+                            cube: next,
+                            normal: Pos::new(-unit.x, -unit.y, -unit.z),
+                            pocket,
+                        }),
+                        Particle::Air => {
+                            *cell = fill;
+                            queue.push_back(next);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            volume
+        }
+
+        pub fn surface(&self) -> Surface {
+            let mut surface = Surface {
+                exterior: 0,
+                interior: 0,
+                pockets: Vec::new(),
+                faces: Vec::new(),
+            };
+            for (bounds, cubes) in self.groups() {
+                let bounds = bounds.expand(1);
+                let mut space = vec![Particle::Air; bounds.volume()];
+                for cube in cubes {
+                    space[bounds.index(cube)] = Particle::Lava;
+                }
+                Self::flood(&mut space, &bounds, bounds.min, Particle::Steam, &mut surface.faces);
+                for i in 0..space.len() {
+                    if space[i] == Particle::Air {
+                        let fill = Particle::Pocket(surface.pockets.len());
+                        let volume = Self::flood(&mut space, &bounds, bounds.pos(i), fill, &mut surface.faces);
+                        surface.pockets.push(Pocket { volume });
+                    }
+                }
+            }
+            surface.interior = surface.faces.iter().filter(|f| f.pocket.is_some()).count();
+            surface.exterior = surface.faces.len() - surface.interior;
+            surface
+        }
     }
 }
 
-fn solve1(positions: &Vec<Pos>) {
-    let set: HashSet<_> = positions.clone().into_iter().collect();
-    let res = set
-        .iter()
-        .flat_map(Pos::around)
-        .filter(|p| !set.contains(p))
-        .count();
-    println!("Solution to problem 1: {res}");
+fn parse_pos(s: &str) -> Pos {
+    let (x, y, z) = s.split(',').flat_map(str::parse).pop3();
+    Pos { x, y, z }
 }
 
-#[derive(PartialEq, Eq, Clone, Copy)]
-enum Particle {
-    Lava,
-    Air,
-    Steam,
+fn group_name(pocket: Option<usize>) -> String {
+    match pocket {
+        Some(id) => format!("pocket_{id}"),
+        None => "exterior".to_string(),
+    }
 }
 
-struct Space(Vec<Vec<Vec<Particle>>>);
+fn write_obj(surface: &Surface, path: &Path) -> io::Result<()> {
+    let mtl_path = path.with_extension("mtl");
+    let mut mtl = BufWriter::new(File::create(&mtl_path)?);
+    writeln!(mtl, "newmtl exterior\nKd 0.90 0.45 0.10\n")?;
+    writeln!(mtl, "newmtl pocket\nKd 0.20 0.50 0.95\nd 0.7")?;
+    mtl.flush()?;
 
-impl Index<Pos> for Space {
-    type Output = Particle;
-    fn index(&self, index: Pos) -> &Self::Output {
-        &self.0[index.z as usize][index.y as usize][index.x as usize]
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "mtllib {}", mtl_path.file_name().unwrap().to_string_lossy())?;
+    let mut vertices = HashMap::new();
+    let mut groups: Vec<(Option<usize>, Vec<[usize; 4]>)> = Vec::new();
+    for (pocket, faces) in &surface.faces.iter().sort_by_key(|f| f.pocket).group_by(|f| f.pocket) {
+        let quads = faces
+            .map(|face| {
+                face.corners().map(|c| {
+                    let next = vertices.len() + 1;
+                    *vertices.entry(c).or_insert_with(|| {
+                        writeln!(out, "v {} {} {}", c.x, c.y, c.z).unwrap();
+                        next
+                    })
+                })
+            })
+            .collect();
+        groups.push((pocket, quads));
+    }
+    for (pocket, quads) in groups {
+        let material = if pocket.is_some() { "pocket" } else { "exterior" };
+        writeln!(out, "g {}\nusemtl {material}", group_name(pocket))?;
+        for [a, b, c, d] in quads {
+            writeln!(out, "f {a} {b} {c} {d}")?;
+        }
     }
+    out.flush()
 }
 
-impl IndexMut<Pos> for Space {
-    fn index_mut(&mut self, index: Pos) -> &mut Self::Output {
-        &mut self.0[index.z as usize][index.y as usize][index.x as usize]
+/// Writes one `solid` per group, as most viewers do not support materials in STL files.
+fn write_stl(surface: &Surface, path: &Path) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for (pocket, faces) in &surface.faces.iter().sort_by_key(|f| f.pocket).group_by(|f| f.pocket) {
+        let name = group_name(pocket);
+        writeln!(out, "solid {name}")?;
+        for face in faces {
+            let n = face.normal;
+            let [a, b, c, d] = face.corners();
+            for triangle in [[a, b, c], [a, c, d]] {
+                writeln!(out, "  facet normal {} {} {}\n    outer loop", n.x, n.y, n.z)?;
+                for v in triangle {
+                    writeln!(out, "      vertex {} {} {}", v.x, v.y, v.z)?;
+                }
+                writeln!(out, "    endloop\n  endfacet")?;
+            }
+        }
+        writeln!(out, "endsolid {name}")?;
     }
+    out.flush()
 }
 
-fn solve2(positions: &Vec<Pos>) {
-    let max_x = positions.iter().max_by_key(|p| p.x).unwrap().x + 5;
-    let max_y = positions.iter().max_by_key(|p| p.y).unwrap().y + 5;
-    let max_z = positions.iter().max_by_key(|p| p.z).unwrap().z + 5;
-    let mut space = Space(vec![
-        vec![vec![Air; max_x as usize]; max_y as usize];
-        max_z as usize
-    ]);
-    for &p in positions {
-        space[p + Pos::new(1, 1, 1)] = Lava;
-    }
-    let mut res = 0;
-    let mut queue = VecDeque::from([Pos::new(0, 0, 0)]);
-    while let Some(p) = queue.pop_front() {
-        if space[p] == Steam {
-            continue;
-        } else if space[p] == Lava {
-            res += 1;
-            continue;
-        }
-        space[p] = Steam;
-        queue.extend(p.around().filter(|p| p.is_clamped(max_x, max_y, max_z)));
-    }
-    println!("Solution to problem 2: {res}");
+fn solve1(voxels: &Voxels) {
+    let res = voxels.surface_area();
+    println!("Solution to problem 1: {res}");
+}
+
+fn solve2(voxels: &Voxels) -> Surface {
+    let surface = voxels.surface();
+    println!("Solution to problem 2: {}", surface.exterior);
+    println!("Interior surface area: {}", surface.interior);
+    print!("Enclosed air pockets: {}", surface.pockets.len());
+    if !surface.pockets.is_empty() {
+        let volumes = surface.pockets.iter().map(|p| p.volume).sort().rev().join(", ");
+        print!(" (volumes: {volumes})");
+    }
+    println!();
+    surface
 }
 
 fn main() {
-    let positions = input!().lines().map(Pos::parse).collect();
-    solve1(&positions);
-    solve2(&positions);
+    let args = Args::parse();
+    let voxels = Voxels::new(input!().lines().map(parse_pos));
+    solve1(&voxels);
+    let surface = solve2(&voxels);
+    if let Some(path) = &args.obj {
+        write_obj(&surface, path).unwrap();
+    }
+    if let Some(path) = &args.stl {
+        write_stl(&surface, path).unwrap();
+    }
 }
@@ -1,121 +1,96 @@
-use std::{
-    ops::{Index, IndexMut},
-    sync::Mutex,
-};
+use std::{collections::HashSet, fmt::Write};
 
+use clap::{Parser, ValueEnum};
 use nom::{
+    branch::alt,
     bytes::complete::tag,
-    character::complete::{multispace0, multispace1, u32},
-    sequence::{pair, preceded, terminated},
+    character::complete::{alpha1, multispace0, multispace1, space1, u32},
+    multi::{many1, separated_list1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
     IResult,
 };
 
-use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 use utils::*;
-use Resource::*;
-
-#[derive(PartialEq, Eq, Clone, Copy)]
-enum Resource {
-    Ore,
-    Clay,
-    Obsidian,
-    Geode,
-}
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
-struct ResourceMap<T> {
-    ore: T,
-    clay: T,
-    obsidian: T,
-    geode: T,
-}
+const MAX_RESOURCES: usize = 8;
 
-impl<T> Index<Resource> for ResourceMap<T> {
-    type Output = T;
-    fn index(&self, index: Resource) -> &Self::Output {
-        match index {
-            Ore => &self.ore,
-            Clay => &self.clay,
-            Obsidian => &self.obsidian,
-            Geode => &self.geode,
-        }
-    }
+const TEST: &str = "Blueprint 1: Each ore robot costs 4 ore. Each clay robot costs 2 ore. Each obsidian robot costs 3 ore and 14 clay. Each geode robot costs 2 ore and 7 obsidian.
+Blueprint 2: Each ore robot costs 2 ore. Each clay robot costs 3 ore. Each obsidian robot costs 3 ore and 8 clay. Each geode robot costs 3 ore and 12 obsidian.";
+
+#[derive(Parser)]
+struct Args {
+    #[arg(value_enum, default_value_t = InputType::Long)]
+    input: InputType,
+    /// Skip states that were already searched, at the cost of memory
+    #[arg(long)]
+    table: bool,
+    /// Narrate the best build order of every blueprint minute by minute
+    #[arg(long)]
+    schedule: bool,
+    /// The resource whose robot you start with
+    #[arg(long, default_value = "ore")]
+    start: String,
+    /// The resource to maximise
+    #[arg(long, default_value = "geode")]
+    objective: String,
 }
 
-impl<T> IndexMut<Resource> for ResourceMap<T> {
-    fn index_mut(&mut self, index: Resource) -> &mut Self::Output {
-        match index {
-            Ore => &mut self.ore,
-            Clay => &mut self.clay,
-            Obsidian => &mut self.obsidian,
-            Geode => &mut self.geode,
-        }
-    }
+#[derive(Clone, ValueEnum)]
+enum InputType {
+    Simple,
+    Long,
 }
 
-impl<'a, T> IntoIterator for &'a ResourceMap<T> {
-    type IntoIter = ResourceIter<'a, T>;
-    type Item = (Resource, &'a T);
-    fn into_iter(self) -> Self::IntoIter {
-        ResourceIter {
-            arr: [
-                (Geode, &self.geode),
-                (Obsidian, &self.obsidian),
-                (Clay, &self.clay),
-                (Ore, &self.ore),
-            ],
-            index: 0,
-        }
-    }
+/// Index into the resource names of a blueprint.
+type Resource = usize;
+type ResourceMap = [u32; MAX_RESOURCES];
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+struct State {
+    robots: ResourceMap,
+    resources: ResourceMap,
 }
 
-struct ResourceIter<'a, T> {
-    arr: [(Resource, &'a T); 4],
-    index: usize,
+/// The minute each robot is built in, in order.
+type Schedule = Vec<(u32, Resource)>;
+
+struct Search {
+    objective: Resource,
+    total_time: u32,
+    best: u32,
+    schedule: Schedule,
+    path: Schedule,
+    table: Option<HashSet<(State, u32)>>,
 }
 
-impl<'a, T> Iterator for ResourceIter<'a, T> {
-    type Item = (Resource, &'a T);
-    fn next(&mut self) -> Option<Self::Item> {
-        self.index += 1;
-        if self.index - 1 < 4 {
-            Some(self.arr[self.index - 1])
-        } else {
-            None
-        }
-    }
+/// A robot collecting `robot` that is built from `costs`.
+struct Recipe {
+    robot: Resource,
+    costs: Vec<(Resource, u32)>,
 }
 
-#[derive(Clone)]
 struct Blueprint {
     id: u32,
-    robot_costs: ResourceMap<Vec<(Resource, u32)>>,
-    robots: ResourceMap<u32>,
-    resources: ResourceMap<u32>,
+    names: Vec<String>,
+    recipes: Vec<Recipe>,
+    max_costs: ResourceMap,
 }
 
-fn parse1<'a>(s: &'a str, robot_type: &'a str) -> IResult<&'a str, u32> {
-    preceded(
-        tag(format!("Each {robot_type} robot costs ").as_str()),
-        terminated(u32, pair(tag(" ore."), multispace0)),
-    )(s)
+fn parse_cost(s: &str) -> IResult<&str, (u32, &str)> {
+    separated_pair(u32, space1, alpha1)(s)
 }
 
-fn parse2<'a>(
-    s: &'a str,
-    robot_type: &'a str,
-    r1: &'a str,
-    r2: &'a str,
-) -> IResult<&'a str, (u32, u32)> {
-    let (s, r1_cost) = preceded(
-        tag(format!("Each {robot_type} robot costs ").as_str()),
-        terminated(u32, tag(format!(" {r1}").as_str())),
-    )(s)?;
-    let (s, r2_cost) = preceded(
-        tag(" and "),
-        terminated(u32, pair(tag(format!(" {r2}.").as_str()), multispace0)),
-    )(s)?;
-    IResult::Ok((s, (r1_cost, r2_cost)))
+/// The robot name and its costs, before names are resolved to indices.
+type ParsedRecipe<'a> = (&'a str, Vec<(u32, &'a str)>);
+
+fn parse_recipe(s: &str) -> IResult<&str, ParsedRecipe<'_>> {
+    pair(
+        delimited(tag("Each "), alpha1, tag(" robot costs ")),
+        terminated(
+            separated_list1(alt((tag(", and "), tag(", "), tag(" and "))), parse_cost),
+            pair(tag("."), multispace0),
+        ),
+    )(s)
 }
 
 impl Blueprint {
@@ -124,103 +99,253 @@ impl Blueprint {
             pair(tag("Blueprint"), multispace1),
             terminated(u32, pair(tag(":"), multispace0)),
         )(s)?;
-        let (s, ore_cost) = parse1(s, "ore")?;
-        let (s, clay_cost) = parse1(s, "clay")?;
-        let (s, (obs_cost_ore, obs_cost_clay)) = parse2(s, "obsidian", "ore", "clay")?;
-        let (s, (geode_cost_ore, geode_cost_obs)) = parse2(s, "geode", "ore", "obsidian")?;
-        let mut robot_costs = ResourceMap::default();
-        robot_costs[Ore] = vec![(Ore, ore_cost)];
-        robot_costs[Clay] = vec![(Ore, clay_cost)];
-        robot_costs[Obsidian] = vec![(Ore, obs_cost_ore), (Clay, obs_cost_clay)];
-        robot_costs[Geode] = vec![(Ore, geode_cost_ore), (Obsidian, geode_cost_obs)];
-        let mut robots = ResourceMap::default();
-        robots[Ore] = 1;
-        let resources = ResourceMap::default();
+        let (s, parsed) = many1(parse_recipe)(s)?;
+        let mut names: Vec<String> = Vec::new();
+        let mut index = |name: &str| match names.iter().position(|n| n == name) {
+            Some(i) => i,
+            None => {
+                names.push(name.to_string());
+                names.len() - 1
+            }
+        };
+        let recipes: Vec<Recipe> = parsed
+            .into_iter()
+            .map(|(robot, costs)| Recipe {
+                robot: index(robot),
+                costs: costs.into_iter().map(|(num, r)| (index(r), num)).collect(),
+            })
+            .collect();
+        assert!(names.len() <= MAX_RESOURCES, "at most {MAX_RESOURCES} resource types are supported");
+        let mut max_costs = ResourceMap::default();
+        for &(r, num) in recipes.iter().flat_map(|recipe| &recipe.costs) {
+            max_costs[r] = max_costs[r].max(num);
+        }
         Ok((
             s,
             Blueprint {
                 id,
-                robot_costs,
-                robots,
-                resources,
+                names,
+                recipes,
+                max_costs,
             },
         ))
     }
 
-    fn available_robots(&self) -> Vec<Resource> {
-        [Geode, Obsidian, Clay, Ore]
-            .into_iter()
-            .filter(|&t| self.can_produce(t))
-            .collect()
+    fn resource(&self, name: &str) -> Option<Resource> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    fn recipe(&self, robot: Resource) -> Option<&Recipe> {
+        self.recipes.iter().find(|recipe| recipe.robot == robot)
     }
 
-    fn can_produce(&self, robot_type: Resource) -> bool {
-        self.robot_costs[robot_type]
+    /// Minutes of collecting needed before `recipe` can be afforded, if the current robots will ever
+    /// produce enough.
+    fn time_to_afford(&self, state: &State, recipe: &Recipe) -> Option<u32> {
+        recipe
+            .costs
             .iter()
-            .all(|&(r, num)| self.resources[r] >= num)
+            .map(|&(r, num)| match (state.resources[r], state.robots[r]) {
+                (have, _) if have >= num => Some(0),
+                (_, 0) => None,
+                (have, robots) => Some((num - have).div_ceil(robots)),
+            })
+            .try_fold(0, |wait, t| t.map(|t| wait.max(t)))
     }
 
-    fn collect_resources(&mut self) {
-        for (robot_type, num) in &self.robots {
-            self.resources[robot_type] += num;
-        }
+    /// More robots of a type than the highest cost in that resource can never be spent, as only one
+    /// robot can be built per minute. The same holds once the stock covers every remaining minute.
+    fn is_useful(&self, state: &State, robot: Resource, time: u32, objective: Resource) -> bool {
+        let (robots, cap) = (state.robots[robot], self.max_costs[robot]);
+        robot == objective || (robots < cap && state.resources[robot] + robots * time < cap * time)
     }
 
-    fn max_potential(&self, time: u32) -> u32 {
-        self.resources[Geode] + self.robots[Geode] * time + (time - 1) * (time) / 2
+    /// As if an objective robot were built every minute from the first minute its costs could be
+    /// afforded, with a new robot for each of the cost resources every minute until then.
+    fn upper_bound(&self, state: &State, time: u32, objective: Resource) -> u32 {
+        let produced = state.resources[objective] + state.robots[objective] * time;
+        let Some(recipe) = self.recipe(objective) else {
+            return produced;
+        };
+        let wait = recipe
+            .costs
+            .iter()
+            .map(|&(r, cost)| {
+                let (mut stock, mut rate, mut wait) = (state.resources[r], state.robots[r], 0);
+                while stock < cost && wait < time {
+                    stock += rate;
+                    rate += 1;
+                    wait += 1;
+                }
+                wait
+            })
+            .max()
+            .unwrap_or(0);
+        let time = time - wait;
+        produced + time.saturating_sub(1) * time / 2
     }
 
-    fn max_geodes(&self, time: u32) -> u32 {
-        self.clone().max_geo_rec(time, 0)
+    fn initial_state(start: Resource) -> State {
+        let mut state = State::default();
+        state.robots[start] = 1;
+        state
     }
 
-    fn max_geo_rec(&mut self, time: u32, max: u32) -> u32 {
-        if time == 0 {
-            return self.resources[Geode];
-        } else if self.max_potential(time) <= max {
-            return 0;
+    fn max_yield(&self, start: Resource, objective: Resource, time: u32, use_table: bool) -> (u32, Schedule) {
+        let mut search = Search {
+            objective,
+            total_time: time,
+            best: 0,
+            schedule: Vec::new(),
+            path: Vec::new(),
+            table: use_table.then(HashSet::new),
         };
-        let new = self.available_robots();
-        self.collect_resources();
-        let backup = self.resources.clone();
-        let mut res = 0;
-        for robot_type in new {
-            self.robots[robot_type] += 1;
-            for &(r, num) in &self.robot_costs[robot_type] {
-                self.resources[r] -= num;
+        self.search(Self::initial_state(start), time, &mut search);
+        (search.best, search.schedule)
+    }
+
+    /// Instead of deciding minute by minute, branches on which robot to build next and skips straight
+    /// to the minute it is finished.
+    fn search(&self, state: State, time: u32, search: &mut Search) {
+        let objective = search.objective;
+        let idle = state.resources[objective] + state.robots[objective] * time;
+        if idle > search.best {
+            search.best = idle;
+            search.schedule = search.path.clone();
+        }
+        if self.upper_bound(&state, time, objective) <= search.best {
+            return;
+        }
+        if let Some(table) = &mut search.table {
+            if !table.insert((state, time)) {
+                return;
+            }
+        }
+        // The objective first, then the other robots from the most to the least advanced.
+        let recipes = self.recipe(objective).into_iter().chain(self.recipes.iter().rev().filter(|r| r.robot != objective));
+        for recipe in recipes {
+            if !self.is_useful(&state, recipe.robot, time, objective) {
+                continue;
+            }
+            let Some(wait) = self.time_to_afford(&state, recipe) else {
+                continue;
+            };
+            if wait + 1 >= time {
+                continue;
+            }
+            let mut next = state;
+            for (r, &num) in state.robots.iter().enumerate() {
+                next.resources[r] += num * (wait + 1);
+            }
+            for &(r, num) in &recipe.costs {
+                next.resources[r] -= num;
+            }
+            next.robots[recipe.robot] += 1;
+            search.path.push((search.total_time - time + wait + 1, recipe.robot));
+            self.search(next, time - wait - 1, search);
+            search.path.pop();
+        }
+    }
+
+    fn robot_name(&self, r: Resource) -> String {
+        match self.names[r].as_str() {
+            "geode" => "geode-cracking robot".to_string(),
+            name => format!("{name}-collecting robot"),
+        }
+    }
+
+    /// Only geodes are counted in the plural in the puzzle text.
+    fn amount(&self, r: Resource, num: u32) -> String {
+        match (self.names[r].as_str(), num) {
+            ("geode", 1) => "1 geode".to_string(),
+            ("geode", _) => format!("{num} geodes"),
+            (name, _) => format!("{num} {name}"),
+        }
+    }
+
+    /// Replays a schedule in the words of the puzzle's example.
+    fn narrate(&self, start: Resource, schedule: &Schedule, time: u32) -> String {
+        let mut state = Self::initial_state(start);
+        let mut builds = schedule.iter().peekable();
+        let mut res = String::new();
+        for minute in 1..=time {
+            writeln!(res, "== Minute {minute} ==").unwrap();
+            let building = builds.next_if(|&&(m, _)| m == minute).map(|&(_, robot)| robot);
+            if let Some(robot) = building {
+                let costs = self
+                    .recipe(robot)
+                    .unwrap()
+                    .costs
+                    .iter()
+                    .map(|&(r, num)| {
+                        state.resources[r] -= num;
+                        self.amount(r, num)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" and ");
+                let name = self.robot_name(robot);
+                let article = if name.starts_with(['a', 'e', 'i', 'o', 'u']) { "an" } else { "a" };
+                writeln!(res, "Spend {costs} to start building {article} {name}.").unwrap();
             }
-            res = res.max(self.max_geo_rec(time - 1, res.max(max)));
-            self.robots[robot_type] -= 1;
-            self.resources = backup.clone();
+            for r in 0..self.names.len() {
+                let robots = state.robots[r];
+                if robots == 0 {
+                    continue;
+                }
+                state.resources[r] += robots;
+                let verb = if self.names[r] == "geode" { "crack" } else { "collect" };
+                let collected = self.amount(r, robots);
+                let (robot, verb) = match robots {
+                    1 => (self.robot_name(r), format!("{verb}s")),
+                    _ => (format!("{}s", self.robot_name(r)), verb.to_string()),
+                };
+                let stock = match self.names[r].as_str() {
+                    "geode" => self.amount(r, state.resources[r]).replacen(' ', " open ", 1),
+                    _ => self.amount(r, state.resources[r]),
+                };
+                writeln!(res, "{robots} {robot} {verb} {collected}; you now have {stock}.").unwrap();
+            }
+            if let Some(robot) = building {
+                state.robots[robot] += 1;
+                let num = state.robots[robot];
+                writeln!(res, "The new {} is ready; you now have {num} of them.", self.robot_name(robot)).unwrap();
+            }
+            res.push('\n');
         }
-        res = res.max(self.max_geo_rec(time - 1, res.max(max)));
-        self.resources = backup.clone();
         res
     }
 }
 
-fn solve1(blueprints: &Vec<Blueprint>) {
-    let progress = Mutex::new(0);
+fn best_yield(blueprint: &Blueprint, time: u32, args: &Args) -> u32 {
+    let resource = |name: &str| {
+        blueprint
+            .resource(name)
+            .unwrap_or_else(|| panic!("blueprint {} has no resource {name}", blueprint.id))
+    };
+    let (start, objective) = (resource(&args.start), resource(&args.objective));
+    let (produced, schedule) = blueprint.max_yield(start, objective, time, args.table);
+    if args.schedule {
+        println!("Blueprint {} in {time} minutes:\n", blueprint.id);
+        print!("{}", blueprint.narrate(start, &schedule, time));
+    }
+    produced
+}
+
+fn solve1(blueprints: &[Blueprint], args: &Args) {
     let res: u32 = blueprints
-        .par_iter()
-        .map(|b| (b.id, b.max_geodes(24)))
-        .inspect(|(id, geodes)| {
-            *progress.lock().unwrap() += 1;
-            println!(
-                "id: {id}\tmax_geodes: {geodes}\tprogress: {}/{}",
-                progress.lock().unwrap(),
-                blueprints.len()
-            )
-        })
+        .iter()
+        .map(|b| (b.id, best_yield(b, 24, args)))
+        .inspect(|(id, geodes)| println!("id: {id}\tmax_geodes: {geodes}"))
         .map(|(id, geodes)| id * geodes)
         .sum();
     println!("Solution to problem 1: {res}");
 }
 
-fn solve2(blueprints: &Vec<Blueprint>) {
-    let res: u32 = blueprints[0..3]
-        .par_iter()
-        .map(|b| (b.id, b.max_geodes(32)))
+fn solve2(blueprints: &[Blueprint], args: &Args) {
+    let res: u32 = blueprints
+        .iter()
+        .take(3)
+        .map(|b| (b.id, best_yield(b, 32, args)))
         .inspect(|(id, geodes)| println!("id: {id}, max geodes: {geodes}"))
         .map(|(_, geodes)| geodes)
         .product();
@@ -228,11 +353,16 @@ fn solve2(blueprints: &Vec<Blueprint>) {
 }
 
 fn main() {
-    let blueprints = input!()
+    let args = Args::parse();
+    let input = match args.input {
+        InputType::Simple => TEST.to_string(),
+        InputType::Long => input!(),
+    };
+    let blueprints: Vec<_> = input
         .lines()
         .flat_map(Blueprint::parse)
         .map(|res| res.1)
         .collect();
-    solve1(&blueprints);
-    solve2(&blueprints);
+    solve1(&blueprints, &args);
+    solve2(&blueprints, &args);
 }
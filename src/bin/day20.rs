@@ -1,7 +1,57 @@
+use clap::{Parser, ValueEnum};
 use itertools::Itertools;
 use utils::*;
 
 const DECRYPT_KEY: isize = 811_589_153;
+const ROUNDS: usize = 10;
+const OFFSETS: [usize; 3] = [1000, 2000, 3000];
+
+const TEST: &str = "1
+2
+-3
+3
+-2
+0
+4";
+
+#[derive(Parser)]
+struct Args {
+    #[arg(value_enum, default_value_t = InputType::Long)]
+    input: InputType,
+    /// Decryption key for part 2
+    #[arg(long, default_value_t = DECRYPT_KEY, allow_negative_numbers = true)]
+    key: isize,
+    /// Rounds of mixing for part 2
+    #[arg(long, default_value_t = ROUNDS)]
+    rounds: usize,
+    /// Positions after the 0 that are summed into the coordinates
+    #[arg(long, value_delimiter = ',', default_values_t = OFFSETS)]
+    offsets: Vec<usize>,
+    /// Print the list like the puzzle's example
+    #[arg(long, value_enum)]
+    trace: Option<Trace>,
+}
+
+#[derive(Clone, ValueEnum)]
+enum InputType {
+    Simple,
+    Long,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Trace {
+    /// After every number that moves
+    Moves,
+    /// After every round of mixing
+    Rounds,
+}
+
+struct MixConfig {
+    key: isize,
+    rounds: usize,
+    offsets: Vec<usize>,
+    trace: Option<Trace>,
+}
 
 #[derive(Clone)]
 struct Elem {
@@ -9,76 +59,175 @@ struct Elem {
     val: isize,
 }
 
+/// The list split into buckets of about `√n` elements, so that an element can be found, removed or
+/// inserted by position in `O(√n)`.
 #[derive(Clone)]
 struct File {
-    len: isize,
-    list: Vec<Elem>,
-    current: usize,
+    len: usize,
+    bucket_size: usize,
+    buckets: Vec<Vec<Elem>>,
+    /// The bucket each element is in, by `init`.
+    bucket_of: Vec<usize>,
+    zero: usize,
 }
 
 impl File {
     fn new(list: Vec<Elem>) -> File {
-        File {
-            len: list.len() as isize,
-            list,
-            current: 0,
+        let len = list.len();
+        let zero = list.iter().find(|e| e.val == 0).unwrap().init;
+        let mut file = File {
+            len,
+            bucket_size: (len as f64).sqrt().ceil().max(1.) as usize,
+            buckets: vec![list],
+            bucket_of: vec![0; len],
+            zero,
+        };
+        file.rebuild();
+        file
+    }
+
+    /// Evens out the buckets again once insertions have made one of them too large.
+    fn rebuild(&mut self) {
+        let list: Vec<Elem> = self.buckets.drain(..).flatten().collect();
+        self.buckets = list.chunks(self.bucket_size).map(<[Elem]>::to_vec).collect();
+        for (b, bucket) in self.buckets.iter().enumerate() {
+            for e in bucket {
+                self.bucket_of[e.init] = b;
+            }
+        }
+    }
+
+    /// The bucket holding position `i` and the offset within it. A position just past the end
+    /// lands at the end of the last bucket, for inserting.
+    fn locate(&self, mut i: usize) -> (usize, usize) {
+        for (b, bucket) in self.buckets.iter().enumerate() {
+            if i < bucket.len() {
+                return (b, i);
+            }
+            i -= bucket.len();
         }
+        let last = self.buckets.len() - 1;
+        (last, self.buckets[last].len())
     }
 
-    fn align(&mut self) {
-        let i = self.list.iter().position(|e| e.val == 0).unwrap();
-        self.list.rotate_left(i);
+    fn get(&self, i: usize) -> &Elem {
+        let (b, j) = self.locate(i);
+        &self.buckets[b][j]
     }
 
-    fn mix(&mut self) {
-        while let Some((i, e)) = self.list.iter().find_position(|e| e.init == self.current) {
-            let dest = ((i as isize + e.val).rem_euclid(self.len - 1)) as usize;
-            if dest < i {
-                self.list[dest..=i].rotate_right(1);
-            } else if dest > i {
-                self.list[i..=dest].rotate_left(1);
+    fn index_of(&self, init: usize) -> usize {
+        let b = self.bucket_of[init];
+        let before: usize = self.buckets[..b].iter().map(Vec::len).sum();
+        before + self.buckets[b].iter().position(|e| e.init == init).unwrap()
+    }
+
+    fn remove(&mut self, i: usize) -> Elem {
+        let (b, j) = self.locate(i);
+        self.buckets[b].remove(j)
+    }
+
+    fn insert(&mut self, i: usize, e: Elem) {
+        let (b, j) = self.locate(i);
+        self.bucket_of[e.init] = b;
+        self.buckets[b].insert(j, e);
+        if self.buckets[b].len() > 2 * self.bucket_size {
+            self.rebuild();
+        }
+    }
+
+    fn mix(&mut self, trace: Option<Trace>) {
+        for init in 0..self.len {
+            let i = self.index_of(init);
+            let e = self.remove(i);
+            // The front and the back are the same place in a circle; the puzzle picks the back.
+            let dest = match (i as isize + e.val).rem_euclid(self.len as isize - 1) as usize {
+                0 => self.len - 1,
+                dest => dest,
+            };
+            let val = e.val;
+            self.insert(dest, e);
+            if trace == Some(Trace::Moves) {
+                if val == 0 {
+                    println!("0 does not move:");
+                } else {
+                    let before = self.get((dest + self.len - 1) % self.len).val;
+                    let after = self.get((dest + 1) % self.len).val;
+                    println!("{val} moves between {before} and {after}:");
+                }
+                println!("{}\n", self.line(0));
             }
-            self.current += 1;
         }
-        self.current = 0;
     }
 
-    fn get_coords(&self) -> isize {
-        [1, 2, 3]
+    /// The values in order, starting at position `from` and wrapping around.
+    fn line(&self, from: usize) -> String {
+        let values = self.buckets.iter().flatten().map(|e| e.val);
+        values.clone().skip(from).chain(values.take(from)).join(", ")
+    }
+
+    /// Counted from the position of the 0.
+    fn get_coords(&self, offsets: &[usize]) -> isize {
+        let zero = self.index_of(self.zero);
+        offsets
             .iter()
-            .map(|&i| self.list[(1000 * i) % self.list.len()].val)
+            .map(|&i| self.get((zero + i) % self.len).val)
             .sum()
     }
+
+    fn decrypt(&mut self, config: &MixConfig) -> isize {
+        for e in self.buckets.iter_mut().flatten() {
+            e.val *= config.key;
+        }
+        if config.trace.is_some() {
+            println!("Initial arrangement:\n{}\n", self.line(0));
+        }
+        for round in 1..=config.rounds {
+            self.mix(config.trace);
+            if config.trace == Some(Trace::Rounds) {
+                let s = if round == 1 { "" } else { "s" };
+                println!("After {round} round{s} of mixing:\n{}\n", self.line(self.index_of(self.zero)));
+            }
+        }
+        self.get_coords(&config.offsets)
+    }
 }
 
-fn solve1(mut file: File) {
-    file.mix();
-    file.align();
-    let res = file.get_coords();
+fn solve1(mut file: File, args: &Args) {
+    let config = MixConfig {
+        key: 1,
+        rounds: 1,
+        offsets: args.offsets.clone(),
+        trace: args.trace,
+    };
+    let res = file.decrypt(&config);
     println!("Solution to problem 1: {res}");
 }
 
-fn solve2(mut file: File) {
-    for e in &mut file.list {
-        e.val *= DECRYPT_KEY;
-    }
-    for _ in 0..10 {
-        file.mix();
-    }
-    file.align();
-    let res = file.get_coords();
+fn solve2(mut file: File, args: &Args) {
+    let config = MixConfig {
+        key: args.key,
+        rounds: args.rounds,
+        offsets: args.offsets.clone(),
+        trace: args.trace,
+    };
+    let res = file.decrypt(&config);
     println!("Solution to problem 2: {res}");
 }
 
 fn main() {
+    let args = Args::parse();
+    let input = match args.input {
+        InputType::Simple => TEST.to_string(),
+        InputType::Long => input!(),
+    };
     let file = File::new(
-        input!()
+        input
             .lines()
             .flat_map(str::parse)
             .enumerate()
             .map(|(i, val)| Elem { init: i, val })
             .collect(),
     );
-    solve1(file.clone());
-    solve2(file);
+    solve1(file.clone(), &args);
+    solve2(file, &args);
 }
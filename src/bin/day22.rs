@@ -1,3 +1,6 @@
+use std::{collections::HashMap, fmt::Write};
+
+use clap::{Parser, ValueEnum};
 use nom::{branch::alt, bytes::complete::tag, character::complete::digit1, multi::many1, IResult};
 use utils::*;
 
@@ -5,7 +8,40 @@ use Direction::*;
 use Instruction::*;
 use Square::*;
 
-#[derive(Clone, Copy)]
+const TEST: &str = "        ...#
+        .#..
+        #...
+        ....
+...#.......#
+........#...
+..#....#....
+..........#.
+        ...#....
+        .....#..
+        .#......
+        ......#.
+
+10R5L5R10L4R5L5";
+
+#[derive(Parser)]
+struct Args {
+    #[arg(value_enum, default_value_t = InputType::Long)]
+    input: InputType,
+    /// Draw the board with the path taken, like the puzzle's example
+    #[arg(long)]
+    render: bool,
+    /// Draw each face of the cube separately, along with what its edges are glued to
+    #[arg(long)]
+    faces: bool,
+}
+
+#[derive(Clone, ValueEnum)]
+enum InputType {
+    Simple,
+    Long,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Direction {
     Left = 2,
     Right = 0,
@@ -13,6 +49,34 @@ enum Direction {
     Down = 1,
 }
 
+/// In the order of their values, clockwise.
+const DIRECTIONS: [Direction; 4] = [Right, Down, Left, Up];
+
+impl Direction {
+    fn turn(self, dir: Direction) -> Direction {
+        let by = if dir == Left { 3 } else { 1 };
+        DIRECTIONS[(self as usize + by) % 4]
+    }
+
+    fn delta(self) -> (isize, isize) {
+        match self {
+            Left => (-1, 0),
+            Right => (1, 0),
+            Up => (0, -1),
+            Down => (0, 1),
+        }
+    }
+
+    fn marker(self) -> char {
+        match self {
+            Left => '<',
+            Right => '>',
+            Up => '^',
+            Down => 'v',
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 enum Instruction {
     Turn(Direction),
@@ -37,158 +101,352 @@ enum Square {
     Null,
 }
 
-struct Walker {
-    instructions: Vec<Instruction>,
-    grid: Vec<Vec<Square>>,
+/// A square on the map and a facing.
+type Pose = (usize, usize, Direction);
+
+/// How the edges of the map are glued together: where a step from a square in a direction leads
+/// and which way one faces afterwards. Walls are left to the walker.
+trait Topology {
+    fn step(&self, x: usize, y: usize, dir: Direction) -> Pose;
+}
+
+/// Stepping off the map comes back in on the opposite side of the same row or column.
+struct Torus {
     bounds_x: Vec<[usize; 2]>,
     bounds_y: Vec<[usize; 2]>,
-    facing: Direction,
-    x: usize,
-    y: usize,
 }
 
-impl Walker {
-    fn parse() -> Walker {
-        let input = input!();
-        let (grid_lines, instructions) = input.split_once("\n\n").unwrap();
-        let grid_lines: Vec<_> = grid_lines.lines().collect();
-        let instructions = many1(Instruction::parse)(instructions).unwrap().1;
-        let max_x = grid_lines.iter().max_by_key(|&l| l.len()).unwrap().len();
-        let mut grid = vec![vec![Null; max_x]; grid_lines.len()];
-        for (y, line) in grid_lines.into_iter().enumerate() {
-            for (x, c) in line.chars().enumerate() {
-                match c {
-                    '.' => grid[y][x] = Empty,
-                    '#' => grid[y][x] = Wall,
-                    _ => {}
-                }
-            }
+impl Torus {
+    fn new(grid: &[Vec<Square>]) -> Torus {
+        let bounds = |squares: &[Square]| {
+            let first = squares.iter().position(|&sqr| sqr != Null).unwrap_or(0);
+            let last = squares.iter().rposition(|&sqr| sqr != Null).unwrap_or(0);
+            [first, last]
+        };
+        Torus {
+            bounds_x: grid.iter().map(|row| bounds(row)).collect(),
+            bounds_y: (0..grid[0].len())
+                .map(|x| bounds(&grid.iter().map(|row| row[x]).collect::<Vec<_>>()))
+                .collect(),
+        }
+    }
+}
+
+impl Topology for Torus {
+    fn step(&self, x: usize, y: usize, dir: Direction) -> Pose {
+        let ([min_x, max_x], [min_y, max_y]) = (self.bounds_x[y], self.bounds_y[x]);
+        let (x, y) = match dir {
+            Left if x == min_x => (max_x, y),
+            Right if x == max_x => (min_x, y),
+            Up if y == min_y => (x, max_y),
+            Down if y == max_y => (x, min_y),
+            _ => (x.wrapping_add_signed(dir.delta().0), y.wrapping_add_signed(dir.delta().1)),
+        };
+        (x, y, dir)
+    }
+}
+
+type Vec3 = [i32; 3];
+
+fn add(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn scale(a: Vec3, k: i32) -> Vec3 {
+    a.map(|c| c * k)
+}
+
+fn dot(a: Vec3, b: Vec3) -> i32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// A face of the net and where it ends up on the cube: the outward normal and the directions its
+/// map's x and y axes point in.
+struct Face {
+    tile: (usize, usize),
+    normal: Vec3,
+    right: Vec3,
+    down: Vec3,
+}
+
+impl Face {
+    fn vector(&self, dir: Direction) -> Vec3 {
+        match dir {
+            Right => self.right,
+            Left => scale(self.right, -1),
+            Down => self.down,
+            Up => scale(self.down, -1),
+        }
+    }
+}
+
+/// The net folded into a cube. Positions on the cube are in doubled units with the cube centred on
+/// the origin, so that the centres of the squares have integer coordinates.
+struct Cube {
+    size: usize,
+    faces: Vec<Face>,
+    face_at: HashMap<(usize, usize), usize>,
+}
+
+impl Cube {
+    /// Folds the paper away from the viewer along every edge shared by two faces of the net.
+    fn fold(grid: &[Vec<Square>]) -> Result<Cube, String> {
+        let area = grid.iter().flatten().filter(|&&sqr| sqr != Null).count();
+        let size = (1..).find(|n| 6 * n * n >= area).unwrap();
+        if 6 * size * size != area {
+            return Err(format!("{area} squares do not make six square faces"));
         }
-        let mut bounds_x = vec![[0, 0]; grid.len()];
-        let mut bounds_y = vec![[0, 0]; max_x];
-        'outer: for y in 0..grid.len() {
-            let mut found_lower = false;
-            for x in 0..max_x {
-                if found_lower && grid[y][x] == Null {
-                    bounds_x[y][1] = x - 1;
-                    continue 'outer;
+        let tile = |x: usize, y: usize| grid.get(y * size)?.get(x * size).filter(|&&sqr| sqr != Null);
+        let start = (0..grid[0].len() / size)
+            .find(|&x| tile(x, 0).is_some())
+            .ok_or("the first row of the map is empty")?;
+        let mut cube = Cube {
+            size,
+            faces: vec![Face {
+                tile: (start, 0),
+                normal: [0, 0, 1],
+                right: [1, 0, 0],
+                down: [0, 1, 0],
+            }],
+            face_at: [((start, 0), 0)].into(),
+        };
+        let mut i = 0;
+        while i < cube.faces.len() {
+            let (x, y) = cube.faces[i].tile;
+            for dir in DIRECTIONS {
+                let (dx, dy) = dir.delta();
+                let (Some(nx), Some(ny)) = (x.checked_add_signed(dx), y.checked_add_signed(dy)) else {
+                    continue;
+                };
+                if tile(nx, ny).is_none() || cube.face_at.contains_key(&(nx, ny)) {
+                    continue;
                 }
-                if !found_lower && grid[y][x] != Null {
-                    bounds_x[y][0] = x;
-                    found_lower = true;
+                let face = &cube.faces[i];
+                let (away, normal) = (scale(face.normal, -1), face.vector(dir));
+                let (right, down) = match dir {
+                    Left | Right => (scale(away, dir.delta().0 as i32), face.down),
+                    Up | Down => (face.right, scale(away, dir.delta().1 as i32)),
+                };
+                if cube.faces.iter().any(|f| f.normal == normal) {
+                    return Err(format!("the face at tile {nx},{ny} overlaps another one"));
                 }
+                cube.face_at.insert((nx, ny), cube.faces.len());
+                cube.faces.push(Face {
+                    tile: (nx, ny),
+                    normal,
+                    right,
+                    down,
+                });
             }
-            bounds_x[y][1] = max_x - 1;
+            i += 1;
         }
-        'outer: for x in 0..max_x {
-            let mut found_lower = false;
-            for y in 0..grid.len() {
-                if found_lower && grid[y][x] == Null {
-                    bounds_y[x][1] = y - 1;
-                    continue 'outer;
-                }
-                if !found_lower && grid[y][x] != Null {
-                    bounds_y[x][0] = y;
-                    found_lower = true;
+        if cube.faces.len() != 6 {
+            return Err(format!("the net has {} connected faces instead of 6", cube.faces.len()));
+        }
+        cube.validate()?;
+        Ok(cube)
+    }
+
+    /// Walking straight ahead from any edge must come back to the same square and facing after going
+    /// once around the cube.
+    fn validate(&self) -> Result<(), String> {
+        let n = self.size;
+        for face in &self.faces {
+            let (x0, y0) = (face.tile.0 * n, face.tile.1 * n);
+            for dir in DIRECTIONS {
+                for k in 0..n {
+                    let start = match dir {
+                        Right => (x0 + n - 1, y0 + k, dir),
+                        Left => (x0, y0 + k, dir),
+                        Down => (x0 + k, y0 + n - 1, dir),
+                        Up => (x0 + k, y0, dir),
+                    };
+                    let (x, y, facing) = (0..4 * n).fold(start, |(x, y, d), _| self.step(x, y, d));
+                    if (x, y, facing) != start {
+                        return Err(format!(
+                            "walking {:?} from {},{} ends at {x},{y} facing {facing:?}",
+                            dir, start.0, start.1
+                        ));
+                    }
                 }
             }
-            bounds_y[x][1] = grid.len() - 1;
         }
-        Walker {
-            x: bounds_x[0][0],
-            y: 0,
-            instructions,
-            grid,
-            bounds_x,
-            bounds_y,
-            facing: Right,
+        Ok(())
+    }
+
+    fn center(&self, face: &Face) -> Vec3 {
+        scale(face.normal, self.size as i32)
+    }
+
+    /// Cuts the drawn board into its faces, each headed by where its edges lead.
+    fn render_faces(&self, drawn: &[Vec<char>]) -> String {
+        let n = self.size;
+        let mut res = String::new();
+        for (id, face) in self.faces.iter().enumerate() {
+            let (x0, y0) = (face.tile.0 * n, face.tile.1 * n);
+            let edges = DIRECTIONS
+                .into_iter()
+                .map(|dir| {
+                    let (x, y) = match dir {
+                        Right => (x0 + n - 1, y0),
+                        Down => (x0, y0 + n - 1),
+                        _ => (x0, y0),
+                    };
+                    let (x, y, facing) = self.step(x, y, dir);
+                    format!("{dir:?} to face {} facing {facing:?}", self.face_at[&(x / n, y / n)])
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(res, "Face {id} at {x0},{y0}: {edges}").unwrap();
+            for row in &drawn[y0..y0 + n] {
+                res.extend(&row[x0..x0 + n]);
+                res.push('\n');
+            }
+            res.push('\n');
         }
+        res
     }
+}
 
-    fn turn(&mut self, dir: Direction) {
-        match self.facing {
-            Left => match dir {
-                Left => self.facing = Down,
-                _ => self.facing = Up,
-            },
-            Right => match dir {
-                Left => self.facing = Up,
-                _ => self.facing = Down,
-            },
-            Up => match dir {
-                Left => self.facing = Left,
-                _ => self.facing = Right,
-            },
-            Down => match dir {
-                Left => self.facing = Right,
-                _ => self.facing = Left,
-            },
+impl Topology for Cube {
+    /// Steps within the net where it is connected, and over the folded edges elsewhere.
+    fn step(&self, x: usize, y: usize, dir: Direction) -> Pose {
+        let n = self.size;
+        let (dx, dy) = dir.delta();
+        let (nx, ny) = (x.wrapping_add_signed(dx), y.wrapping_add_signed(dy));
+        if self.face_at.contains_key(&(nx / n, ny / n)) {
+            return (nx, ny, dir);
         }
+        let from = &self.faces[self.face_at[&(x / n, y / n)]];
+        let offset = |i: usize| 2 * i as i32 - (n as i32 - 1);
+        let pos = add(
+            self.center(from),
+            add(scale(from.right, offset(x % n)), scale(from.down, offset(y % n))),
+        );
+        let normal = from.vector(dir);
+        let pos = add(pos, add(normal, scale(from.normal, -1)));
+        let to = self.faces.iter().find(|f| f.normal == normal).unwrap();
+        let facing = DIRECTIONS
+            .into_iter()
+            .find(|&d| to.vector(d) == scale(from.normal, -1))
+            .unwrap();
+        let rel = add(pos, scale(self.center(to), -1));
+        let local = |axis: Vec3| ((dot(rel, axis) + n as i32 - 1) / 2) as usize;
+        (to.tile.0 * n + local(to.right), to.tile.1 * n + local(to.down), facing)
     }
+}
 
-    fn walk(&mut self, dist: usize) {
-        let mut pos = [self.x, self.y];
-        let bounds_x = self.bounds_x[pos[1]];
-        let bounds_y = self.bounds_y[pos[0]];
-        for _ in 0..dist {
-            match self.facing {
-                Left => {
-                    pos[0] = if pos[0] == bounds_x[0] {
-                        bounds_x[1]
-                    } else {
-                        pos[0] - 1
-                    }
-                }
-                Right => {
-                    pos[0] = if pos[0] == bounds_x[1] {
-                        bounds_x[0]
-                    } else {
-                        pos[0] + 1
-                    }
-                }
-                Up => {
-                    pos[1] = if pos[1] == bounds_y[0] {
-                        bounds_y[1]
-                    } else {
-                        pos[1] - 1
-                    };
-                }
-                Down => {
-                    pos[1] = if pos[1] == bounds_y[1] {
-                        bounds_y[0]
-                    } else {
-                        pos[1] + 1
-                    }
+struct Board {
+    instructions: Vec<Instruction>,
+    grid: Vec<Vec<Square>>,
+}
+
+impl Board {
+    fn parse(input: &str) -> Result<Board, String> {
+        let (grid_lines, instructions) = input.split_once("\n\n").ok_or("missing the path")?;
+        let instructions = many1(Instruction::parse)(instructions.trim()).map_err(|e| e.to_string())?.1;
+        let grid_lines: Vec<_> = grid_lines.lines().collect();
+        let max_x = grid_lines.iter().map(|l| l.len()).max().unwrap_or(0);
+        let mut grid = vec![vec![Null; max_x]; grid_lines.len()];
+        for (y, line) in grid_lines.into_iter().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                match c {
+                    '.' => grid[y][x] = Empty,
+                    '#' => grid[y][x] = Wall,
+                    _ => {}
                 }
-            };
-            if self.grid[pos[1]][pos[0]] == Wall {
-                return;
             }
-            self.x = pos[0];
-            self.y = pos[1];
         }
+        Ok(Board { instructions, grid })
     }
 
-    fn simulate(&mut self) {
-        self.facing = Right;
-        let instructions = self.instructions.clone();
-        self.x = self.bounds_x[0][0];
-        self.y = 0;
-        for i in instructions {
+    /// Follows the path from the leftmost open square of the top row, logging every square visited
+    /// and every turn.
+    fn walk(&self, topology: &impl Topology) -> Vec<Pose> {
+        let x = self.grid[0].iter().position(|&sqr| sqr == Empty).unwrap();
+        let mut path = vec![(x, 0, Right)];
+        for &i in &self.instructions {
+            let (mut x, mut y, mut facing) = *path.last().unwrap();
             match i {
-                Turn(dir) => self.turn(dir),
-                Walk(dist) => self.walk(dist),
+                Turn(dir) => path.push((x, y, facing.turn(dir))),
+                Walk(dist) => {
+                    for _ in 0..dist {
+                        let (nx, ny, next_facing) = topology.step(x, y, facing);
+                        if self.grid[ny][nx] == Wall {
+                            break;
+                        }
+                        (x, y, facing) = (nx, ny, next_facing);
+                        path.push((x, y, facing));
+                    }
+                }
             }
         }
+        path
     }
 
-    fn calc_password(&self) -> usize {
-        (self.y + 1) * 1000 + (self.x + 1) * 4 + self.facing as usize
+    /// The map with the last facing in every square visited.
+    fn draw(&self, path: &[Pose]) -> Vec<Vec<char>> {
+        let mut drawn: Vec<Vec<char>> = self
+            .grid
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|sqr| match sqr {
+                        Empty => '.',
+                        Wall => '#',
+                        Null => ' ',
+                    })
+                    .collect()
+            })
+            .collect();
+        for &(x, y, facing) in path {
+            drawn[y][x] = facing.marker();
+        }
+        drawn
+    }
+}
+
+fn render(drawn: &[Vec<char>]) -> String {
+    drawn
+        .iter()
+        .map(|row| row.iter().collect::<String>().trim_end().to_string() + "\n")
+        .collect()
+}
+
+fn calc_password(&(x, y, facing): &Pose) -> usize {
+    (y + 1) * 1000 + (x + 1) * 4 + facing as usize
+}
+
+fn solve1(board: &Board, args: &Args) {
+    let path = board.walk(&Torus::new(&board.grid));
+    if args.render {
+        println!("{}", render(&board.draw(&path)));
+    }
+    let res = calc_password(path.last().unwrap());
+    println!("Solution to problem 1: {res}");
+}
+
+fn solve2(board: &Board, args: &Args) {
+    let cube = Cube::fold(&board.grid).unwrap_or_else(|e| panic!("invalid cube net: {e}"));
+    let path = board.walk(&cube);
+    let drawn = board.draw(&path);
+    if args.render {
+        println!("{}", render(&drawn));
+    }
+    if args.faces {
+        print!("{}", cube.render_faces(&drawn));
     }
+    let res = calc_password(path.last().unwrap());
+    println!("Solution to problem 2: {res}");
 }
 
 fn main() {
-    let mut walker = Walker::parse();
-    walker.simulate();
-    println!("Solution to problem 1: {}", walker.calc_password());
+    let args = Args::parse();
+    let input = match args.input {
+        InputType::Simple => TEST.to_string(),
+        InputType::Long => input!(),
+    };
+    let board = Board::parse(&input).unwrap();
+    solve1(&board, &args);
+    solve2(&board, &args);
 }
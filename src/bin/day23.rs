@@ -1,102 +1,436 @@
 use std::{
-    collections::{HashMap, HashSet},
-    ops::Add,
+    fmt::Display,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
-use itertools::Itertools;
+use clap::{value_parser, Parser};
+use termion::{cursor::Goto, cursor::HideCursor, event::Key, input::TermRead, raw::IntoRawMode};
 use utils::*;
 
 const NUM_ROUNDS: usize = 10;
+const MAX_ROUNDS: usize = 5_000;
+const ORDER: &str = "NSWE";
+/// Empty ground added on each side when the elves get close to the edge.
+const GROW_ROWS: usize = 16;
+const FPS: u64 = 10;
+const MAX_FPS: u64 = 1000;
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
 
-const N: Pos = Pos(0, -1);
-const S: Pos = Pos(0, 1);
-const W: Pos = Pos(-1, 0);
-const E: Pos = Pos(1, 0);
-const NW: Pos = Pos(-1, -1);
-const NE: Pos = Pos(1, -1);
-const SW: Pos = Pos(-1, 1);
-const SE: Pos = Pos(1, 1);
+const CONTROLS: &str = "space: pause  s: step  +/-: speed  q: quit";
 
-#[derive(Clone, Copy, Hash, PartialEq, Eq)]
-struct Pos(isize, isize);
+const TEST: &str = "....#..
+..###.#
+#...#.#
+.#...##
+#.###..
+##.#.##
+.#..#..";
 
-impl Add<Pos> for Pos {
-    type Output = Pos;
-    fn add(self, rhs: Pos) -> Self::Output {
-        Pos(self.0 + rhs.0, self.1 + rhs.1)
-    }
+#[derive(Parser)]
+struct Args {
+    #[arg(value_enum, default_value_t = InputType::Long)]
+    input: InputType,
+    /// Rounds to spread for part 1
+    #[arg(long, default_value_t = NUM_ROUNDS)]
+    rounds: usize,
+    /// Give up on part 2 if the elves are still moving after this many rounds
+    #[arg(long, default_value_t = MAX_ROUNDS)]
+    max_rounds: usize,
+    /// The directions considered in the first round, in order, as some of `N`, `S`, `W` and `E`
+    #[arg(long, default_value = ORDER)]
+    order: Order,
+    /// Draw the elves after these rounds, with 0 for the scan
+    #[arg(long, value_delimiter = ',')]
+    show: Vec<usize>,
+    /// Write how many elves moved and how far they have spread after every round
+    #[arg(long)]
+    csv: Option<PathBuf>,
+    /// Animate the elves spreading out in the terminal
+    #[arg(long)]
+    animate: bool,
+    #[arg(long, default_value_t = FPS, value_parser = value_parser!(u64).range(1..))]
+    fps: u64,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+enum InputType {
+    Simple,
+    Long,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Dir {
+    N,
+    S,
+    W,
+    E,
 }
 
-impl Pos {
-    fn around(&self) -> impl Iterator<Item = Pos> + '_ {
-        (-1..=1)
-            .cartesian_product(-1..=1)
-            .filter(|&p| p != (0, 0))
-            .map(move |(x, y)| *self + Pos(x, y))
+#[derive(Clone)]
+struct Order(Vec<Dir>);
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Order, String> {
+        let order: Vec<Dir> = s
+            .chars()
+            .map(|c| match c {
+                'N' => Ok(Dir::N),
+                'S' => Ok(Dir::S),
+                'W' => Ok(Dir::W),
+                'E' => Ok(Dir::E),
+                c => Err(format!("unknown direction {c}")),
+            })
+            .collect::<Result<_, _>>()?;
+        if order.is_empty() {
+            return Err("at least one direction is needed".to_string());
+        }
+        if (1..order.len()).any(|i| order[..i].contains(&order[i])) {
+            return Err("directions can only be given once".to_string());
+        }
+        Ok(Order(order))
     }
 }
 
-fn perform_move(directions: &[[Pos; 3]; 4], positions: &mut HashSet<Pos>) -> bool {
-    let mut moves: HashMap<_, _> = positions.iter().map(|&p| (p, p)).collect();
-    for &p in positions.iter() {
-        if !p.around().any(|p2| positions.contains(&p2)) {
-            continue;
+type Row = Vec<u64>;
+
+/// Moves every bit one square east, so that each square holds what was to its west.
+fn east(row: &[u64]) -> Row {
+    (0..row.len())
+        .map(|w| row[w] << 1 | if w > 0 { row[w - 1] >> 63 } else { 0 })
+        .collect()
+}
+
+/// Moves every bit one square west, so that each square holds what was to its east.
+fn west(row: &[u64]) -> Row {
+    (0..row.len())
+        .map(|w| row[w] >> 1 | row.get(w + 1).map_or(0, |&next| next << 63))
+        .collect()
+}
+
+fn zip(a: &[u64], b: &[u64], f: impl Fn(u64, u64) -> u64) -> Row {
+    a.iter().zip(b).map(|(&a, &b)| f(a, b)).collect()
+}
+
+fn or(a: &[u64], b: &[u64]) -> Row {
+    zip(a, b, |a, b| a | b)
+}
+
+fn and_not(a: &[u64], b: &[u64]) -> Row {
+    zip(a, b, |a, b| a & !b)
+}
+
+/// The elves as one bit per square, row by row, with empty ground around them so none can step
+/// off the edge in a round.
+struct Grove {
+    rows: Vec<Row>,
+    words: usize,
+}
+
+impl Grove {
+    fn parse(input: &str) -> Grove {
+        let lines: Vec<_> = input.lines().collect();
+        let width = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+        let words = width.div_ceil(64).max(1);
+        let rows = lines
+            .iter()
+            .map(|l| {
+                let mut row = vec![0; words];
+                for (x, _) in l.char_indices().filter(|&(_, c)| c == '#') {
+                    row[x / 64] |= 1 << (x % 64);
+                }
+                row
+            })
+            .collect();
+        let mut grove = Grove {
+            rows,
+            words,
+        };
+        grove.grow();
+        grove
+    }
+
+    fn count(&self) -> usize {
+        self.rows.iter().flatten().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Rows and bit columns holding elves, as `(first, last)` pairs.
+    fn extent(&self) -> Option<((usize, usize), (usize, usize))> {
+        let first_y = self.rows.iter().position(|row| row.iter().any(|&w| w != 0))?;
+        let last_y = self.rows.iter().rposition(|row| row.iter().any(|&w| w != 0))?;
+        let all = self.rows.iter().fold(vec![0; self.words], |acc, row| or(&acc, row));
+        let first_w = all.iter().position(|&w| w != 0)?;
+        let last_w = all.iter().rposition(|&w| w != 0)?;
+        let first_x = first_w * 64 + all[first_w].trailing_zeros() as usize;
+        let last_x = last_w * 64 + 63 - all[last_w].leading_zeros() as usize;
+        Some(((first_x, last_x), (first_y, last_y)))
+    }
+
+    /// Width and height of the smallest rectangle holding every elf.
+    fn size(&self) -> (usize, usize) {
+        self.extent().map_or((0, 0), |((x0, x1), (y0, y1))| (x1 - x0 + 1, y1 - y0 + 1))
+    }
+
+    fn empty_ground(&self) -> usize {
+        let (width, height) = self.size();
+        width * height - self.count()
+    }
+
+    fn contains(&self, x: usize, y: usize) -> bool {
+        self.rows[y][x / 64] >> (x % 64) & 1 == 1
+    }
+
+    /// Adds empty rows and words of columns on any side that elves have reached.
+    fn grow(&mut self) {
+        let Some(((x0, x1), (y0, y1))) = self.extent() else {
+            return;
+        };
+        if y0 == 0 || y1 + 1 == self.rows.len() {
+            let empty = vec![vec![0; self.words]; GROW_ROWS];
+            self.rows.splice(0..0, empty.clone());
+            self.rows.extend(empty);
+        }
+        if x0 == 0 || x1 + 1 == self.words * 64 {
+            for row in &mut self.rows {
+                row.insert(0, 0);
+                row.push(0);
+            }
+            self.words += 2;
         }
-        let check = |dirs: &[Pos]| dirs.iter().any(|&d| positions.contains(&(p + d)));
-        for dirs in directions {
-            if !check(dirs) {
-                moves.insert(p, p + dirs[0]);
-                break;
+    }
+
+    /// Plays one round and returns how many elves moved.
+    fn round(&mut self, order: &[Dir]) -> usize {
+        let empty = vec![0; self.words];
+        let row = |rows: &[Row], y: isize| {
+            usize::try_from(y).ok().and_then(|y| rows.get(y)).unwrap_or(&empty).clone()
+        };
+        let height = self.rows.len();
+        // Proposals of every row, by direction in the order of `Dir`.
+        let proposals: Vec<[Row; 4]> = (0..height)
+            .map(|y| {
+                let (n, c) = (row(&self.rows, y as isize - 1), &self.rows[y]);
+                let s = row(&self.rows, y as isize + 1);
+                let n3 = or(&or(&n, &east(&n)), &west(&n));
+                let s3 = or(&or(&s, &east(&s)), &west(&s));
+                let ncs = or(&or(&n, c), &s);
+                let (w3, e3) = (east(&ncs), west(&ncs));
+                let crowded = or(&or(&n3, &s3), &or(&w3, &e3));
+                let mut waiting = zip(c, &crowded, |c, crowded| c & crowded);
+                let mut proposed: [Row; 4] = Default::default();
+                for &dir in order {
+                    let blocked = match dir {
+                        Dir::N => &n3,
+                        Dir::S => &s3,
+                        Dir::W => &w3,
+                        Dir::E => &e3,
+                    };
+                    proposed[dir as usize] = and_not(&waiting, blocked);
+                    waiting = and_not(&waiting, &proposed[dir as usize]);
+                }
+                proposed.map(|p| if p.is_empty() { empty.clone() } else { p })
+            })
+            .collect();
+        let proposal = |y: isize, dir: Dir| {
+            usize::try_from(y)
+                .ok()
+                .and_then(|y| proposals.get(y))
+                .map_or(empty.clone(), |p| p[dir as usize].clone())
+        };
+        // With every direction checking its diagonals, two elves can only propose the same square
+        // from opposite sides of it.
+        let mut moved = 0;
+        let mut next = vec![empty.clone(); height];
+        for (y, [north, south, west_, east_]) in proposals.iter().enumerate() {
+            let n = and_not(north, &proposal(y as isize - 2, Dir::S));
+            let s = and_not(south, &proposal(y as isize + 2, Dir::N));
+            let w = and_not(west_, &east(&east(east_)));
+            let e = and_not(east_, &west(&west(west_)));
+            let leaving = or(&or(&n, &s), &or(&w, &e));
+            moved += leaving.iter().map(|w| w.count_ones() as usize).sum::<usize>();
+            next[y] = or(&or(&next[y], &and_not(&self.rows[y], &leaving)), &or(&west(&w), &east(&e)));
+            if y > 0 {
+                next[y - 1] = or(&next[y - 1], &n);
+            }
+            if y + 1 < height {
+                next[y + 1] = or(&next[y + 1], &s);
             }
         }
+        self.rows = next;
+        self.grow();
+        moved
+    }
+}
+
+/// Draws the smallest rectangle holding every elf.
+impl Display for Grove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(((x0, x1), (y0, y1))) = self.extent() else {
+            return Ok(());
+        };
+        for y in y0..=y1 {
+            let line: String = (x0..=x1).map(|x| if self.contains(x, y) { '#' } else { '.' }).collect();
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
     }
-    let mut found_new = false;
-    let valid_next: HashSet<_> = moves
-        .values()
-        .counts()
-        .into_iter()
-        .filter(|&(_, c)| c == 1)
-        .map(|t| *t.0)
-        .collect();
-    *positions = moves
-        .into_iter()
-        .map(|(from, to)| {
-            if valid_next.contains(&to) {
-                found_new = found_new || from != to;
-                to
-            } else {
-                from
+}
+
+/// The grove along with the direction order of the next round.
+struct Spread {
+    grove: Grove,
+    order: Vec<Dir>,
+    round: usize,
+    moved: usize,
+}
+
+impl Spread {
+    fn new(grove: Grove, order: &[Dir]) -> Spread {
+        Spread {
+            grove,
+            order: order.to_vec(),
+            round: 0,
+            moved: 0,
+        }
+    }
+
+    fn step(&mut self) {
+        self.moved = self.grove.round(&self.order);
+        self.order.rotate_left(1);
+        self.round += 1;
+    }
+
+    fn settled(&self) -> bool {
+        self.round > 0 && self.moved == 0
+    }
+
+    /// Everything the next rounds depend on, wherever in the grove the elves are.
+    fn snapshot(&self) -> (String, Vec<Dir>) {
+        (self.grove.to_string(), self.order.clone())
+    }
+}
+
+fn animate(mut spread: Spread, mut fps: u64) {
+    let mut stdout = HideCursor::from(io::stdout().into_raw_mode().unwrap());
+    let mut keys = termion::async_stdin().keys();
+    let mut paused = false;
+    let mut last_tick = Instant::now();
+    let mut redraw = true;
+    'anim: loop {
+        let mut steps = 0;
+        for key in keys.by_ref().flatten() {
+            match key {
+                Key::Char('q') | Key::Esc | Key::Ctrl('c') => break 'anim,
+                Key::Char(' ') | Key::Char('p') => paused = !paused,
+                Key::Char('s') | Key::Char('.') => {
+                    paused = true;
+                    steps += 1;
+                }
+                Key::Char('+') | Key::Char('=') => fps = (fps * 2).min(MAX_FPS),
+                Key::Char('-') => fps = (fps / 2).max(1),
+                _ => continue,
+            }
+            redraw = true;
+        }
+        if !paused && last_tick.elapsed() >= Duration::from_millis(1000 / fps) {
+            steps += 1;
+        }
+        if steps > 0 && !spread.settled() {
+            for _ in 0..steps {
+                spread.step();
+            }
+            last_tick = Instant::now();
+            redraw = true;
+        }
+        if redraw {
+            let (cols, rows) = termion::terminal_size().unwrap_or((80, 24));
+            let state = match (spread.settled(), paused) {
+                (true, _) => "settled",
+                (_, true) => "paused",
+                _ => "running",
+            };
+            let (width, height) = spread.grove.size();
+            let status = format!(
+                "round {} | moved {} | {width}x{height} | empty {} | fps: {fps} | {state} | {CONTROLS}",
+                spread.round,
+                spread.moved,
+                spread.grove.empty_ground()
+            );
+            let status: String = status.chars().take(cols as usize).collect();
+            write!(stdout, "{}{}", termion::clear::All, Goto(1, 1)).unwrap();
+            for line in spread.grove.to_string().lines().take(rows.saturating_sub(1) as usize) {
+                let line: String = line.chars().take(cols as usize).collect();
+                write!(stdout, "{line}\r\n").unwrap();
             }
-        })
-        .collect();
-    found_new
-} 
+            write!(stdout, "{}{status}", Goto(1, rows)).unwrap();
+            stdout.flush().unwrap();
+            redraw = false;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    write!(stdout, "{}{}{}", termion::clear::All, Goto(1, 1), termion::cursor::Show).unwrap();
+    stdout.flush().unwrap();
+}
 
 fn main() {
-    let input = input!();
-    let mut positions: HashSet<_> = input
-        .lines()
-        .enumerate()
-        .flat_map(|(y, l)| {
-            l.chars()
-                .enumerate()
-                .filter(|&(_, c)| c == '#')
-                .map(move |(x, _)| Pos(x as isize, y as isize))
-        })
-        .collect();
-    let mut directions = [[N, NE, NW], [S, SE, SW], [W, NW, SW], [E, NE, SE]];
-    for round in 1.. {
-        if !perform_move(&directions, &mut positions) {
-            println!("Solution to problem 2: {}", round);
-            return;
-        } else if round == NUM_ROUNDS {
-            let minmax =
-                |f: fn(&Pos) -> isize| positions.iter().map(f).minmax().into_option().unwrap();
-            let (min_x, max_x) = minmax(|p| p.0);
-            let (min_y, max_y) = minmax(|p| p.1);
-            let res = (max_x - min_x + 1) * (max_y - min_y + 1) - positions.len() as isize;
-            println!("Solution to problem 1: {res}");
+    let args = Args::parse();
+    let input = match args.input {
+        InputType::Simple => TEST.to_string(),
+        InputType::Long => input!(),
+    };
+    let mut spread = Spread::new(Grove::parse(&input), &args.order.0);
+    if args.animate {
+        animate(spread, args.fps);
+        return;
+    }
+    let mut csv = args.csv.as_ref().map(|path| BufWriter::new(File::create(path).unwrap()));
+    if let Some(out) = &mut csv {
+        writeln!(out, "round,moved,width,height,empty_ground").unwrap();
+    }
+    if args.show.contains(&0) {
+        println!("== Initial State ==\n{}", spread.grove);
+    }
+    let part1 = |spread: &Spread| println!("Solution to problem 1: {}", spread.grove.empty_ground());
+    // Some orders keep the elves moving forever. Comparing every round with the one at the last power
+    // of two finds any repeat once that round is past both the start and the length of the cycle.
+    let mut saved = spread.snapshot();
+    let mut repeated = false;
+    while !spread.settled() {
+        if spread.round == args.rounds {
+            part1(&spread);
+        }
+        if repeated && spread.round >= args.rounds {
+            eprintln!("The elves never settle: round {} repeats an earlier one", spread.round);
+            std::process::exit(1);
+        }
+        if spread.round == args.max_rounds {
+            eprintln!("The elves are still moving after {} rounds", spread.round);
+            std::process::exit(1);
         }
-        directions.rotate_left(1);
+        spread.step();
+        if spread.round.is_power_of_two() {
+            saved = spread.snapshot();
+        } else {
+            repeated |= spread.snapshot() == saved;
+        }
+        let round = spread.round;
+        if let Some(out) = &mut csv {
+            let (width, height) = spread.grove.size();
+            let empty = spread.grove.empty_ground();
+            writeln!(out, "{round},{},{width},{height},{empty}", spread.moved).unwrap();
+        }
+        if args.show.contains(&round) {
+            println!("== End of Round {round} ==\n{}", spread.grove);
+        }
+    }
+    // Nothing moves any more once the elves are settled, so the grove stays as it is.
+    if spread.round <= args.rounds {
+        part1(&spread);
     }
+    println!("Solution to problem 2: {}", spread.round);
 }
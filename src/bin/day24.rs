@@ -1,123 +1,409 @@
-use std::{collections::HashSet, ops::Index};
+use std::{fmt::Display, str::FromStr};
 
-use itertools::Itertools;
+use clap::{error::ErrorKind, CommandFactory, Parser, ValueEnum};
+use num::integer::lcm;
 use utils::*;
-use Blizzard::*;
 
-const UNITS: [Pos; 5] = [(0, 0), (1, 0), (0, 1), (-1, 0), (0, -1)];
+const TEST: &str = "#.######
+#>>.<^<#
+#.<..<<#
+#>v.><>#
+#<^v^^>#
+######.#";
 
-const START_POS: Pos = (0, -1);
+#[derive(Parser)]
+struct Args {
+    #[arg(value_enum, default_value_t = InputType::Long)]
+    input: InputType,
+    /// Plan a trip through these places instead, each `start`, `goal` or `x:y` counted from the top
+    /// left square inside the walls
+    #[arg(long, value_delimiter = ',')]
+    trip: Vec<Waypoint>,
+    /// Print the moves of every leg
+    #[arg(long)]
+    route: bool,
+}
 
-#[derive(Clone, Copy)]
-enum Blizzard {
-    Left,
-    Right,
-    Up,
-    Down,
+#[derive(Clone, ValueEnum)]
+enum InputType {
+    Simple,
+    Long,
 }
 
+/// Positions are counted from the top left square inside the walls, so the openings are in the
+/// rows `-1` and `height`.
 type Pos = (isize, isize);
 
-struct Grid {
-    grid: Vec<Vec<Vec<Blizzard>>>,
-    time: usize,
+#[derive(Clone, Copy)]
+enum Waypoint {
+    Start,
+    Goal,
+    At(Pos),
 }
 
-impl Index<Pos> for Grid {
-    type Output = Vec<Blizzard>;
-    fn index(&self, index: Pos) -> &Self::Output {
-        &self.grid[index.1 as usize][index.0 as usize]
+impl FromStr for Waypoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Waypoint, String> {
+        match s {
+            "start" => Ok(Waypoint::Start),
+            "goal" => Ok(Waypoint::Goal),
+            s => {
+                let (x, y) = s.split_once(':').ok_or(format!("expected x:y, got {s}"))?;
+                let coord = |c: &str| c.trim().parse().map_err(|_| format!("invalid coordinate {c}"));
+                Ok(Waypoint::At((coord(x)?, coord(y)?)))
+            }
+        }
     }
 }
 
-impl Grid {
-    fn parse(s: String) -> Grid {
-        let grid = s
-            .lines()
-            .map(|l| {
-                l.chars()
-                    .filter_map(|c| match c {
-                        '.' => Some(vec![]),
-                        '<' => Some(vec![Left]),
-                        '>' => Some(vec![Right]),
-                        '^' => Some(vec![Up]),
-                        'v' => Some(vec![Down]),
-                        _ => None,
-                    })
-                    .collect_vec()
-            })
-            .filter(|r| r.len() > 1)
-            .collect();
-        Grid { grid, time: 0 }
+/// One bit per square of a row of the valley.
+type Row = u128;
+const MAX_WIDTH: usize = Row::BITS as usize;
+
+/// The blizzards of each direction as they are at minute 0, one row at a time. A blizzard always
+/// comes back to where it started, so at minute `t` the rows are just rotated by `t`, and the
+/// whole valley repeats every `lcm(width, height)` minutes.
+struct Valley {
+    width: usize,
+    height: usize,
+    left: Vec<Row>,
+    right: Vec<Row>,
+    up: Vec<Row>,
+    down: Vec<Row>,
+    start: Pos,
+    goal: Pos,
+}
+
+/// The squares the expedition could be in at some minute.
+#[derive(Clone)]
+struct Frontier {
+    rows: Vec<Row>,
+    start: bool,
+    goal: bool,
+}
+
+impl Frontier {
+    fn union(&mut self, other: &Frontier) {
+        for (row, other) in self.rows.iter_mut().zip(&other.rows) {
+            *row |= other;
+        }
+        self.start |= other.start;
+        self.goal |= other.goal;
+    }
+
+    fn is_subset(&self, other: &Frontier) -> bool {
+        self.rows.iter().zip(&other.rows).all(|(row, other)| row & !other == 0)
+            && (!self.start || other.start)
+            && (!self.goal || other.goal)
+    }
+}
+
+impl Valley {
+    fn parse(s: &str) -> Result<Valley, String> {
+        let lines: Vec<_> = s.lines().collect();
+        if lines.len() < 3 {
+            return Err("the valley needs walls on both sides".to_string());
+        }
+        let opening = |line: &str, y: isize| {
+            let x = line.find('.').ok_or("a wall has no opening")?;
+            Ok::<_, String>((x as isize - 1, y))
+        };
+        let inner = &lines[1..lines.len() - 1];
+        let (width, height) = (lines[0].len() - 2, inner.len());
+        if width > MAX_WIDTH {
+            return Err(format!("the valley is wider than {MAX_WIDTH} squares"));
+        }
+        let mut valley = Valley {
+            width,
+            height,
+            left: vec![0; height],
+            right: vec![0; height],
+            up: vec![0; height],
+            down: vec![0; height],
+            start: opening(lines[0], -1)?,
+            goal: opening(lines[lines.len() - 1], height as isize)?,
+        };
+        for (y, line) in inner.iter().enumerate() {
+            for (x, c) in line.chars().skip(1).take(width).enumerate() {
+                let rows = match c {
+                    '<' => &mut valley.left,
+                    '>' => &mut valley.right,
+                    '^' => &mut valley.up,
+                    'v' => &mut valley.down,
+                    _ => continue,
+                };
+                rows[y] |= 1 << x;
+            }
+        }
+        Ok(valley)
+    }
+
+    fn mask(&self) -> Row {
+        Row::MAX >> (MAX_WIDTH - self.width)
     }
 
-    fn len(&self) -> Pos {
-        (self.grid[0].len() as isize, self.grid.len() as isize)
+    /// Moves every bit `by` squares to higher `x`, wrapping around within the valley.
+    fn rotate(&self, row: Row, by: usize) -> Row {
+        let by = by % self.width;
+        if by == 0 {
+            return row;
+        }
+        (row << by | row >> (self.width - by)) & self.mask()
     }
 
-    fn step_time(&mut self) {
-        let len = self.len().map(|l| l as usize);
-        let prev = std::mem::replace(&mut self.grid, vec![vec![vec![]; len.0]; len.1]);
-        for (y, row) in prev.into_iter().enumerate() {
-            for (x, sqr) in row.into_iter().enumerate() {
-                for bliz in sqr {
-                    match bliz {
-                        Left => self.grid[y][(x - 1).min(len.0 - 1)].push(Left),
-                        Right => self.grid[y][(x + 1) % len.0].push(Right),
-                        Up => self.grid[(y - 1).min(len.1 - 1)][x].push(Up),
-                        Down => self.grid[(y + 1) % len.1][x].push(Down),
-                    }
+    /// The squares of row `y` without a blizzard at minute `t`.
+    fn free_row(&self, y: usize, t: usize) -> Row {
+        let (w, h) = (self.width, self.height);
+        let blizzards = self.rotate(self.left[y], w - t % w)
+            | self.rotate(self.right[y], t)
+            | self.up[(y + t) % h]
+            | self.down[(y + h - t % h) % h];
+        !blizzards & self.mask()
+    }
+
+    fn period(&self) -> usize {
+        lcm(self.width, self.height)
+    }
+
+    fn is_free(&self, (x, y): Pos, t: usize) -> bool {
+        if (x, y) == self.start || (x, y) == self.goal {
+            return true;
+        }
+        self.contains((x, y)) && self.free_row(y as usize, t) >> x & 1 == 1
+    }
+
+    fn contains(&self, (x, y): Pos) -> bool {
+        (0..self.width as isize).contains(&x) && (0..self.height as isize).contains(&y)
+    }
+
+    /// The square inside the walls next to an opening.
+    fn entry(&self, (x, y): Pos) -> Pos {
+        (x, if y < 0 { 0 } else { y - 1 })
+    }
+
+    fn nowhere(&self) -> Frontier {
+        Frontier {
+            rows: vec![0; self.height],
+            start: false,
+            goal: false,
+        }
+    }
+
+    fn frontier(&self, p: Pos) -> Frontier {
+        let mut frontier = Frontier {
+            rows: vec![0; self.height],
+            start: p == self.start,
+            goal: p == self.goal,
+        };
+        if self.contains(p) {
+            frontier.rows[p.1 as usize] |= 1 << p.0;
+        }
+        frontier
+    }
+
+    fn reached(&self, frontier: &Frontier, p: Pos) -> bool {
+        match p {
+            p if p == self.start => frontier.start,
+            p if p == self.goal => frontier.goal,
+            (x, y) => frontier.rows[y as usize] >> x & 1 == 1,
+        }
+    }
+
+    /// Everywhere the expedition can be at minute `t + 1` from `frontier` at minute `t`. The
+    /// openings are never hit by a blizzard, so whoever is there can stay.
+    fn advance(&self, frontier: &Frontier, t: usize) -> Frontier {
+        let mask = self.mask();
+        let rows = &frontier.rows;
+        let mut next: Vec<Row> = (0..self.height)
+            .map(|y| {
+                let mut row = rows[y] | rows[y] << 1 | rows[y] >> 1;
+                if y > 0 {
+                    row |= rows[y - 1];
                 }
+                if y + 1 < self.height {
+                    row |= rows[y + 1];
+                }
+                row & mask & self.free_row(y, t + 1)
+            })
+            .collect();
+        for (opening, inside) in [(self.start, frontier.start), (self.goal, frontier.goal)] {
+            let (x, y) = self.entry(opening);
+            if inside {
+                next[y as usize] |= 1 << x & self.free_row(y as usize, t + 1);
             }
         }
-        self.time += 1;
+        let next_to = |p: Pos| {
+            let (x, y) = self.entry(p);
+            rows[y as usize] >> x & 1 == 1
+        };
+        Frontier {
+            rows: next,
+            start: frontier.start || next_to(self.start),
+            goal: frontier.goal || next_to(self.goal),
+        }
     }
 
-    fn valid_pos(&self, p: &Pos) -> bool {
-        (p.0 >= 0 && p.0 < self.len().0 && p.1 >= 0 && p.1 < self.len().1)
-            || *p == START_POS
-            || *p == self.goal()
+    /// Every square `p` could have been in a minute earlier.
+    fn neighbours(&self, (x, y): Pos) -> impl Iterator<Item = Pos> + '_ {
+        [(0, 0), (1, 0), (0, 1), (-1, 0), (0, -1)]
+            .into_iter()
+            .map(move |(dx, dy)| (x + dx, y + dy))
+            .filter(|&p| self.contains(p) || p == self.start || p == self.goal)
     }
 
-    fn get_next(&self, from: Pos) -> impl Iterator<Item = Pos> + '_ {
-        UNITS
-            .iter()
-            .map(move |p| (from.0 + p.0, from.1 + p.1))
-            .filter(|p| self.valid_pos(p))
+    fn resolve(&self, waypoint: Waypoint) -> Result<Pos, String> {
+        match waypoint {
+            Waypoint::Start => Ok(self.start),
+            Waypoint::Goal => Ok(self.goal),
+            Waypoint::At(p) if self.contains(p) || p == self.start || p == self.goal => Ok(p),
+            Waypoint::At((x, y)) => Err(format!("{x}:{y} is not inside the valley")),
+        }
     }
 
-    fn bfs(&mut self, start: Pos, goal: Pos) -> usize {
-        let mut next_positions = HashSet::from([start]);
+    /// The fastest trip through all `waypoints` in order, leaving the first one at minute 0.
+    ///
+    /// Arriving somewhere as early as possible is not always best for the next leg, so every leg
+    /// is searched at once: layer `k` holds the squares reachable after the first `k` waypoints have
+    /// been visited, and whenever it reaches waypoint `k + 1` that square joins layer `k + 1`. The
+    /// valley repeats every period, so there are only so many states of leg, square and minute within
+    /// the period, and the search gives up after the first minute that brings no new one.
+    fn plan_trip(&self, waypoints: &[Waypoint]) -> Result<Vec<Leg>, String> {
+        let waypoints = waypoints
+            .iter()
+            .map(|&w| self.resolve(w))
+            .collect::<Result<Vec<_>, _>>()?;
+        let Some(num_legs) = waypoints.len().checked_sub(1).filter(|&n| n > 0) else {
+            return Ok(Vec::new());
+        };
+        let period = self.period();
+        let mut layers = vec![self.nowhere(); num_legs + 1];
+        if self.is_free(waypoints[0], 0) {
+            layers[0] = self.frontier(waypoints[0]);
+        }
+        let mut seen = vec![vec![self.nowhere(); num_legs + 1]; period];
+        let mut history = Vec::new();
+        let mut furthest = 0;
         loop {
-            let current = std::mem::take(&mut next_positions);
-            self.step_time();
-            for p in current {
-                for next in self.get_next(p) {
-                    if next == goal {
-                        return self.time;
-                    } else if next == START_POS || next == self.goal() {
-                        next_positions.insert(next);
-                    } else if !self[next].is_empty() {
-                        continue;
-                    } else {
-                        next_positions.insert(next);
-                    }
+            let t = history.len();
+            for k in 0..num_legs {
+                if self.reached(&layers[k], waypoints[k + 1]) {
+                    layers[k + 1].union(&self.frontier(waypoints[k + 1]));
+                    furthest = furthest.max(k + 1);
+                }
+            }
+            if self.reached(&layers[num_legs], waypoints[num_legs]) {
+                history.push(layers);
+                break;
+            }
+            let mut new = false;
+            for (seen, layer) in seen[t % period].iter_mut().zip(&layers) {
+                if !layer.is_subset(seen) {
+                    seen.union(layer);
+                    new = true;
                 }
             }
+            if !new {
+                let ((x0, y0), (x1, y1)) = (waypoints[furthest], waypoints[furthest + 1]);
+                return Err(format!("{x1}:{y1} cannot be reached from {x0}:{y0}"));
+            }
+            let next = layers.iter().map(|layer| self.advance(layer, t)).collect();
+            history.push(std::mem::replace(&mut layers, next));
+        }
+
+        // Walk back from the last waypoint, waiting and moving within a leg for as long as
+        // possible, so each leg starts when the expedition sets off for its waypoint.
+        let (mut k, mut p, mut t) = (num_legs - 1, waypoints[num_legs], history.len() - 1);
+        let mut routes = vec![Vec::new(); num_legs];
+        routes[k].push(p);
+        while t > 0 || k > 0 {
+            let prev = (t > 0)
+                .then(|| self.neighbours(p).find(|&q| self.reached(&history[t - 1][k], q)))
+                .flatten();
+            match prev {
+                Some(q) => (p, t) = (q, t - 1),
+                None => k -= 1,
+            }
+            routes[k].push(p);
+        }
+        let mut legs: Vec<Leg> = Vec::new();
+        for mut route in routes {
+            route.reverse();
+            let start = legs.last().map_or(0, Leg::end);
+            legs.push(Leg { start, route });
         }
+        Ok(legs)
+    }
+}
+
+/// Part of a trip, leaving at minute `start` and then at `route[i]` at minute `start + i`.
+struct Leg {
+    start: usize,
+    route: Vec<Pos>,
+}
+
+impl Leg {
+    fn end(&self) -> usize {
+        self.start + self.route.len() - 1
     }
 
-    fn goal(&self) -> Pos {
-        (self.len().0, self.len().1 - 1)
+    /// The puzzle's blizzard arrows for each move, with `.` for waiting.
+    fn moves(&self) -> String {
+        self.route
+            .array_windows()
+            .map(|&[(x0, y0), (x1, y1)]| match (x1 - x0, y1 - y0) {
+                (1, 0) => '>',
+                (-1, 0) => '<',
+                (0, 1) => 'v',
+                (0, -1) => '^',
+                _ => '.',
+            })
+            .collect()
+    }
+}
+
+impl Display for Leg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ((x0, y0), (x1, y1)) = (self.route[0], self.route[self.route.len() - 1]);
+        write!(
+            f,
+            "{x0}:{y0} -> {x1}:{y1} from minute {} to {} ({} minutes)",
+            self.start,
+            self.end(),
+            self.end() - self.start
+        )
     }
 }
 
 fn main() {
-    let mut grid = Grid::parse(input!());
-    let res = grid.bfs(START_POS, grid.goal());
+    let args = Args::parse();
+    let input = match args.input {
+        InputType::Simple => TEST.to_string(),
+        InputType::Long => input!(),
+    };
+    let valley = Valley::parse(&input).unwrap_or_else(|e| panic!("{e}"));
+    if !args.trip.is_empty() {
+        let legs = valley
+            .plan_trip(&args.trip)
+            .unwrap_or_else(|e| Args::command().error(ErrorKind::ValueValidation, e).exit());
+        for (i, leg) in legs.iter().enumerate() {
+            println!("Leg {}: {leg}", i + 1);
+            if args.route {
+                println!("{}", leg.moves());
+            }
+        }
+        return;
+    }
+    let trip = |waypoints: &[Waypoint]| match valley.plan_trip(waypoints) {
+        Ok(legs) => legs.last().map_or(0, Leg::end),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let res = trip(&[Waypoint::Start, Waypoint::Goal]);
     println!("Solution to problem 1: {res}");
 
-    grid.bfs(grid.goal(), START_POS);
-    let res = grid.bfs(START_POS, grid.goal());
+    let res = trip(&[Waypoint::Start, Waypoint::Goal, Waypoint::Start, Waypoint::Goal]);
     println!("Solution to problem 2: {res}");
 }
@@ -1,72 +1,260 @@
-use std::ops::Add;
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display},
+    iter::Sum,
+    marker::PhantomData,
+    ops::{Add, Mul, Neg, Sub},
+    str::FromStr,
+};
 
-use itertools::EitherOrBoth::*;
-use itertools::Itertools;
+use num::{BigInt, Signed, ToPrimitive, Zero};
 use utils::*;
 
-fn to_digit(c: char) -> i8 {
-    match c {
-        '0' => 0,
-        '1' => 1,
-        '2' => 2,
-        '-' => -1,
-        '=' => -2,
-        _ => panic!(),
-    }
+/// The digits of a balanced base from the lowest to the highest value. The base is the number of
+/// digits, which has to be odd so that the digits are symmetric around 0, and at least 3.
+trait Alphabet {
+    const DIGITS: &'static str;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Quinary;
+
+impl Alphabet for Quinary {
+    const DIGITS: &'static str = "=-012";
+}
+
+type Snafu = Balanced<Quinary>;
+
+/// A number in a balanced base, as digits from the least significant one, without leading zeros.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Balanced<A> {
+    digits: Vec<i8>,
+    alphabet: PhantomData<A>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ParseError {
+    Empty,
+    InvalidDigit { digit: char, index: usize },
 }
 
-fn to_char(d: &i8) -> char {
-    match d {
-        0 => '0',
-        1 => '1',
-        2 => '2',
-        -1 => '-',
-        -2 => '=',
-        _ => panic!(),
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "cannot parse a number from an empty string"),
+            ParseError::InvalidDigit { digit, index } => write!(f, "invalid digit {digit:?} at {index}"),
+        }
     }
 }
 
-struct SNAFU(Vec<i8>);
+#[derive(Debug, PartialEq, Eq)]
+struct OutOfRange;
+
+impl<A: Alphabet> Balanced<A> {
+    fn base() -> i128 {
+        let base = A::DIGITS.chars().count() as i128;
+        assert!(base % 2 == 1, "a balanced base needs an odd number of digits");
+        assert!(base >= 3, "a balanced base needs at least 3 digits");
+        base
+    }
+
+    fn half() -> i128 {
+        Self::base() / 2
+    }
 
-impl SNAFU {
-    fn from_str(s: &str) -> SNAFU {
-        SNAFU(s.chars().rev().map(to_digit).collect())
+    fn zero() -> Balanced<A> {
+        Balanced {
+            digits: Vec::new(),
+            alphabet: PhantomData,
+        }
+    }
+
+    /// Carries any out of range digits over, so each ends up within `-half..=half`.
+    fn normalize(values: impl IntoIterator<Item = i128>) -> Balanced<A> {
+        let (base, half) = (Self::base(), Self::half());
+        let mut digits = Vec::new();
+        let mut carry = 0;
+        let mut values = values.into_iter();
+        loop {
+            let value = match values.next() {
+                Some(v) => v + carry,
+                None if carry != 0 => carry,
+                None => break,
+            };
+            let mut digit = value.rem_euclid(base);
+            if digit > half {
+                digit -= base;
+            }
+            carry = (value - digit) / base;
+            digits.push(digit as i8);
+        }
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        Balanced {
+            digits,
+            alphabet: PhantomData,
+        }
     }
 
-    fn to_string(&self) -> String {
-        self.0.iter().rev().map(to_char).collect()
+    /// The sign of the number is the sign of its highest digit.
+    fn signum(&self) -> i8 {
+        self.digits.last().map_or(0, |d| d.signum())
     }
 }
 
-impl Add<SNAFU> for SNAFU {
-    type Output = SNAFU;
-    fn add(self, rhs: SNAFU) -> Self::Output {
-        let mut rem = 0;
-        let mut vec = self
-            .0
-            .into_iter() 
-            .zip_longest(rhs.0)
-            .map(|t| {
-                let res = match t {
-                    Both(x, y) => x + y,
-                    Left(x) | Right(x) => x,
-                } + rem;
-                rem = (res < -2 || res > 2) as i8 * res.signum();
-                -5 * rem + res
+impl<A: Alphabet> FromStr for Balanced<A> {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ParseError::Empty);
+        }
+        let half = Self::half();
+        let values = s
+            .chars()
+            .enumerate()
+            .map(|(index, digit)| match A::DIGITS.chars().position(|c| c == digit) {
+                Some(i) => Ok(i as i128 - half),
+                None => Err(ParseError::InvalidDigit { digit, index }),
             })
-            .collect_vec();
-        if rem != 0 {
-            vec.push(rem);
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::normalize(values.into_iter().rev()))
+    }
+}
+
+impl<A: Alphabet> Display for Balanced<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let half = Self::half();
+        let char_of = |d: i128| A::DIGITS.chars().nth((d + half) as usize).unwrap();
+        if self.digits.is_empty() {
+            return write!(f, "{}", char_of(0));
         }
-        SNAFU(vec)
+        let s: String = self.digits.iter().rev().map(|&d| char_of(d as i128)).collect();
+        f.pad(&s)
+    }
+}
+
+impl<A: Alphabet> From<i64> for Balanced<A> {
+    fn from(n: i64) -> Self {
+        Self::normalize([n as i128])
+    }
+}
+
+impl<A: Alphabet> TryFrom<&Balanced<A>> for i64 {
+    type Error = OutOfRange;
+
+    fn try_from(n: &Balanced<A>) -> Result<i64, OutOfRange> {
+        // A prefix of the digits can be out of range even when the whole number is not.
+        let base = Balanced::<A>::base();
+        let n = n.digits.iter().rev().try_fold(0i128, |acc, &d| {
+            acc.checked_mul(base).and_then(|acc| acc.checked_add(d as i128)).ok_or(OutOfRange)
+        })?;
+        i64::try_from(n).map_err(|_| OutOfRange)
+    }
+}
+
+impl<A: Alphabet> TryFrom<Balanced<A>> for i64 {
+    type Error = OutOfRange;
+
+    fn try_from(n: Balanced<A>) -> Result<i64, OutOfRange> {
+        i64::try_from(&n)
+    }
+}
+
+impl<A: Alphabet> From<&Balanced<A>> for BigInt {
+    fn from(n: &Balanced<A>) -> BigInt {
+        let base = BigInt::from(Balanced::<A>::base());
+        n.digits.iter().rev().fold(BigInt::zero(), |acc, &d| acc * &base + d)
+    }
+}
+
+impl<A: Alphabet> From<&BigInt> for Balanced<A> {
+    fn from(n: &BigInt) -> Self {
+        let base = BigInt::from(Self::base());
+        let mut values = Vec::new();
+        let mut rest = n.abs();
+        while !rest.is_zero() {
+            values.push((&rest % &base).to_i128().unwrap());
+            rest /= &base;
+        }
+        let res = Self::normalize(values);
+        if n.is_negative() {
+            -res
+        } else {
+            res
+        }
+    }
+}
+
+impl<A: Alphabet> Add for Balanced<A> {
+    type Output = Balanced<A>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        let len = self.digits.len().max(rhs.digits.len());
+        let digit = |n: &Self, i: usize| n.digits.get(i).copied().unwrap_or(0) as i128;
+        Self::normalize((0..len).map(|i| digit(&self, i) + digit(&rhs, i)))
+    }
+}
+
+impl<A: Alphabet> Neg for Balanced<A> {
+    type Output = Balanced<A>;
+
+    fn neg(mut self) -> Self::Output {
+        for d in &mut self.digits {
+            *d = -*d;
+        }
+        self
+    }
+}
+
+impl<A: Alphabet> Sub for Balanced<A> {
+    type Output = Balanced<A>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self + -rhs
+    }
+}
+
+impl<A: Alphabet> Mul for Balanced<A> {
+    type Output = Balanced<A>;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let len = (self.digits.len() + rhs.digits.len()).saturating_sub(1);
+        let mut values = vec![0; len];
+        for (i, &a) in self.digits.iter().enumerate() {
+            for (j, &b) in rhs.digits.iter().enumerate() {
+                values[i + j] += a as i128 * b as i128;
+            }
+        }
+        Self::normalize(values)
+    }
+}
+
+impl<A: Alphabet> Sum for Balanced<A> {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |sum, n| sum + n)
+    }
+}
+
+impl<A: Alphabet + Clone + Eq> Ord for Balanced<A> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.clone() - other.clone()).signum().cmp(&0)
+    }
+}
+
+impl<A: Alphabet + Clone + Eq> PartialOrd for Balanced<A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
 fn main() {
-    let res = input!()
+    let numbers: Vec<Snafu> = input!()
         .lines()
-        .map(SNAFU::from_str)
-        .reduce(|sum, n| sum + n)
-        .unwrap();
-    println!("Solution: {}", res.to_string());
+        .map(|l| l.parse().unwrap_or_else(|e| panic!("{l}: {e}")))
+        .collect();
+    let res: Snafu = numbers.into_iter().sum();
+    println!("Fuel needed: {}", BigInt::from(&res));
+    println!("Solution: {res}");
 }